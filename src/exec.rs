@@ -0,0 +1,194 @@
+//! Running a command with secrets from encrypted files in its environment.
+//!
+//! Each '--env-file' is decrypted in memory and parsed according to its name: '.json', '.yaml' and '.yml' files hold a
+//! single object of names to strings, numbers or booleans, and anything else is read as a dotenv file. The variables
+//! are only given to the child process. They're never written to disk or set in ironhide's own environment.
+
+use crate::{file::decrypt, util};
+use clap::Parser;
+use ironoxide::prelude::*;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+const EXAMPLES: &str = "EXAMPLES
+
+    Start a server with the variables from an encrypted dotenv file.
+        $ ironhide exec --env-file .env.iron -- npm start
+
+    Variables from later files override those from earlier ones.
+        $ ironhide exec --env-file common.yaml.iron --env-file prod.json.iron -- ./deploy.sh --verbose
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Run a command with the variables from encrypted dotenv, JSON or YAML files added to its environment. The files are
+/// decrypted in memory and the variables are only given to the command. Exits with the command's exit code.
+pub struct Exec {
+    /// Path of an encrypted file holding variables for the command. The format is chosen by the file's extension,
+    /// e.g. 'secrets.json.iron' is JSON and 'config.yml.iron' is YAML. Anything else is read as a dotenv file. Can be
+    /// provided more than once, with variables from later files overriding those from earlier ones.
+    #[clap(value_parser = clap::value_parser!(PathBuf), long = "env-file", value_name = "FILE", required = true)]
+    env_files: Vec<PathBuf>,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+    /// Command to run, along with its arguments. Must follow '--'.
+    #[clap(value_parser = clap::value_parser!(OsString), value_name = "COMMAND", num_args = 1.., required = true, last = true)]
+    command: Vec<OsString>,
+}
+
+impl util::GetKeyfile for Exec {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+/// Run the command with the decrypted variables, returning the exit code ironhide should exit with.
+pub fn exec(
+    sdk: &BlockingIronOxide,
+    Exec {
+        env_files, command, ..
+    }: Exec,
+) -> Result<i32, String> {
+    let mut vars = BTreeMap::new();
+    for path in &env_files {
+        let contents = decrypt::decrypt_to_memory(sdk, path)?;
+        let parsed = parse_env(Format::for_path(path), &contents)
+            .map_err(|e| format!("Couldn't read variables from '{}': {e}", path.display()))?;
+        vars.extend(parsed);
+    }
+
+    let (program, args) = command.split_first().expect("clap requires a command");
+    // interrupts from the terminal go straight to the command, and other signals are passed on to it
+    let exit = util::run_in_foreground(Command::new(program).args(args).envs(&vars))
+        .map_err(|e| format!("Couldn't run '{}': {e}", program.to_string_lossy()))?;
+    Ok(exit_code(exit.status))
+}
+
+/// Format of a decrypted env file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Dotenv,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Chosen by the last two extensions of the file's name, so the format shows through a '.iron' or custom suffix.
+    fn for_path(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let extensions: Vec<_> = name.split('.').skip(1).collect();
+        extensions
+            .iter()
+            .rev()
+            .take(2)
+            .find_map(|extension| match *extension {
+                "json" => Some(Format::Json),
+                "yaml" | "yml" => Some(Format::Yaml),
+                _ => None,
+            })
+            .unwrap_or(Format::Dotenv)
+    }
+}
+
+fn parse_env(format: Format, contents: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let vars = match format {
+        Format::Dotenv => dotenvy::from_read_iter(contents)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?,
+        Format::Json => from_object(serde_json::from_slice(contents).map_err(|e| e.to_string())?)?,
+        Format::Yaml => from_object(serde_yaml::from_slice(contents).map_err(|e| e.to_string())?)?,
+    };
+    vars.into_iter().map(check_var).collect()
+}
+
+fn from_object(object: Map<String, Value>) -> Result<Vec<(String, String)>, String> {
+    object
+        .into_iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => Ok((name, value)),
+            Value::Number(value) => Ok((name, value.to_string())),
+            Value::Bool(value) => Ok((name, value.to_string())),
+            _ => Err(format!(
+                "Value of '{name}' must be a string, number or boolean."
+            )),
+        })
+        .collect()
+}
+
+fn check_var((name, value): (String, String)) -> Result<(String, String), String> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        Err(format!("'{name}' isn't a valid environment variable name."))
+    } else if value.contains('\0') {
+        Err(format!("Value of '{name}' contains a NUL character."))
+    } else {
+        Ok((name, value))
+    }
+}
+
+/// Exit code of the command, or 128 plus the signal number if it was killed by a signal, as shells report it.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_file_name() {
+        assert_eq!(
+            Format::for_path(Path::new("secrets.json.iron")),
+            Format::Json
+        );
+        assert_eq!(
+            Format::for_path(Path::new("dir/app.YML.iron")),
+            Format::Yaml
+        );
+        assert_eq!(Format::for_path(Path::new("config.yaml")), Format::Yaml);
+        assert_eq!(Format::for_path(Path::new(".env.iron")), Format::Dotenv);
+        assert_eq!(Format::for_path(Path::new("json.iron")), Format::Dotenv);
+        assert_eq!(Format::for_path(Path::new("prod.env")), Format::Dotenv);
+    }
+
+    #[test]
+    fn parses_every_format() {
+        let expected = vec![
+            ("API_KEY".to_string(), "abc=123".to_string()),
+            ("DEBUG".to_string(), "true".to_string()),
+            ("PORT".to_string(), "8080".to_string()),
+        ];
+        let dotenv = b"# comment\nAPI_KEY=\"abc=123\"\nDEBUG=true\nexport PORT=8080\n";
+        let json = br#"{"API_KEY": "abc=123", "DEBUG": true, "PORT": 8080}"#;
+        let yaml = b"API_KEY: abc=123\nDEBUG: true\nPORT: 8080\n";
+        for (format, contents) in [
+            (Format::Dotenv, &dotenv[..]),
+            (Format::Json, json),
+            (Format::Yaml, yaml),
+        ] {
+            let mut vars = parse_env(format, contents).unwrap();
+            vars.sort();
+            assert_eq!(vars, expected);
+        }
+
+        assert!(parse_env(Format::Json, br#"["not", "an", "object"]"#).is_err());
+        assert!(parse_env(Format::Json, br#"{"NESTED": {"a": 1}}"#).is_err());
+        assert!(parse_env(Format::Yaml, b"LIST:\n  - a\n").is_err());
+        assert!(parse_env(Format::Json, br#"{"A=B": "c"}"#).is_err());
+        assert!(parse_env(Format::Dotenv, b"NOT A VARIABLE\n").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! ASCII armor for encrypted files, so they can be pasted into email, tickets or chat.
+//!
+//! Armored output wraps the encrypted bytes in a PEM-style block of base64 lines, followed by a line holding a CRC-24
+//! checksum of the binary data (the same checksum OpenPGP uses) so that damage from copy and paste is caught before
+//! decryption is attempted:
+//!
+//! ```text
+//! -----BEGIN IRONHIDE MESSAGE-----
+//! AgAweyJfZGlkXyI6IjE2YmI5...
+//! =Jd8p
+//! -----END IRONHIDE MESSAGE-----
+//! ```
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    path::Path,
+};
+
+const BEGIN: &str = "-----BEGIN IRONHIDE MESSAGE-----";
+const END: &str = "-----END IRONHIDE MESSAGE-----";
+/// Number of binary bytes encoded on each line, which works out to 64 base64 characters.
+const BYTES_PER_LINE: usize = 48;
+/// Leading whitespace allowed before the armor header, e.g. from a pasted email.
+const MAX_LEADING_WHITESPACE: usize = 1024;
+const CRC24_INIT: u32 = 0xB704CE;
+const CRC24_POLY: u32 = 0x1864CFB;
+
+fn crc24(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+/// Writes armored output to an inner writer. `finish` must be called to write the checksum and footer.
+pub struct ArmorWriter<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+    crc: u32,
+}
+
+impl<W: Write> ArmorWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        writeln!(inner, "{BEGIN}")?;
+        Ok(ArmorWriter {
+            inner,
+            pending: Vec::with_capacity(BYTES_PER_LINE),
+            crc: CRC24_INIT,
+        })
+    }
+
+    /// Write any partial last line, the checksum and the footer, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            writeln!(self.inner, "{}", STANDARD.encode(&self.pending))?;
+        }
+        let checksum = STANDARD.encode(&self.crc.to_be_bytes()[1..]);
+        writeln!(self.inner, "={checksum}")?;
+        writeln!(self.inner, "{END}")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ArmorWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc = crc24(self.crc, buf);
+        for byte in buf {
+            self.pending.push(*byte);
+            if self.pending.len() == BYTES_PER_LINE {
+                writeln!(self.inner, "{}", STANDARD.encode(&self.pending))?;
+                self.pending.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hand `writer` to `write`, wrapping it in armor first if `armor` is set.
+pub fn write_output<T>(
+    writer: &mut dyn Write,
+    armor: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<T, String>,
+) -> Result<T, String> {
+    if armor {
+        let write_err = |e: io::Error| format!("Couldn't write encrypted file: {e}");
+        let mut armored = ArmorWriter::new(writer).map_err(write_err)?;
+        let result = write(&mut armored)?;
+        armored.finish().map_err(write_err)?;
+        Ok(result)
+    } else {
+        write(writer)
+    }
+}
+
+/// Check whether the file at `path` holds an armored message.
+pub fn is_armored_file(path: &Path) -> io::Result<bool> {
+    let mut prefix = Vec::new();
+    fs::File::open(path)?
+        .take((MAX_LEADING_WHITESPACE + BEGIN.len()) as u64)
+        .read_to_end(&mut prefix)?;
+    Ok(is_armored(&prefix))
+}
+
+fn is_armored(prefix: &[u8]) -> bool {
+    prefix.trim_ascii_start().starts_with(BEGIN.as_bytes())
+}
+
+/// Wrap `reader` so armored input is decoded transparently. Input that isn't armored is passed through unchanged.
+pub fn dearmor<R: Read + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    // peek past any leading whitespace to see whether the armor header is next
+    let mut prefix = Vec::new();
+    let mut byte = [0u8; 1];
+    while prefix.len() < MAX_LEADING_WHITESPACE && reader.read(&mut byte)? == 1 {
+        prefix.push(byte[0]);
+        if !byte[0].is_ascii_whitespace() {
+            break;
+        }
+    }
+    (&mut reader)
+        .take((BEGIN.len() - 1) as u64)
+        .read_to_end(&mut prefix)?;
+    let armored = is_armored(&prefix);
+    let replayed = Cursor::new(prefix).chain(reader);
+    if armored {
+        Ok(Box::new(ArmorReader::new(BufReader::new(replayed))))
+    } else {
+        Ok(Box::new(replayed))
+    }
+}
+
+enum ArmorState {
+    Header,
+    Body,
+    Footer,
+    Done,
+}
+
+/// Decodes armored input a line at a time, verifying the checksum before reporting the end of the data.
+struct ArmorReader<R: BufRead> {
+    inner: R,
+    state: ArmorState,
+    decoded: Vec<u8>,
+    position: usize,
+    crc: u32,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid armored message: {message}"),
+    )
+}
+
+impl<R: BufRead> ArmorReader<R> {
+    fn new(inner: R) -> Self {
+        ArmorReader {
+            inner,
+            state: ArmorState::Header,
+            decoded: Vec::new(),
+            position: 0,
+            crc: CRC24_INIT,
+        }
+    }
+
+    /// Read lines until more data has been decoded or the end of the message has been verified.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        while self.position == self.decoded.len() && !matches!(self.state, ArmorState::Done) {
+            line.clear();
+            if self.inner.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "armored message is truncated",
+                ));
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match self.state {
+                ArmorState::Header if line == BEGIN => self.state = ArmorState::Body,
+                ArmorState::Header => return Err(invalid("missing header")),
+                ArmorState::Body if line.starts_with('=') => {
+                    let checksum = STANDARD
+                        .decode(&line[1..])
+                        .map_err(|_| invalid("malformed checksum"))?;
+                    if checksum != self.crc.to_be_bytes()[1..] {
+                        return Err(invalid("checksum mismatch, the message may be damaged"));
+                    }
+                    self.state = ArmorState::Footer;
+                }
+                ArmorState::Body if line == END => return Err(invalid("missing checksum")),
+                ArmorState::Body => {
+                    self.decoded = STANDARD
+                        .decode(line)
+                        .map_err(|_| invalid("malformed base64"))?;
+                    self.position = 0;
+                    self.crc = crc24(self.crc, &self.decoded);
+                }
+                ArmorState::Footer if line == END => self.state = ArmorState::Done,
+                ArmorState::Footer => return Err(invalid("missing footer")),
+                ArmorState::Done => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ArmorReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill()?;
+        let available = &self.decoded[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn armor(bytes: &[u8]) -> String {
+        let mut writer = ArmorWriter::new(Vec::new()).unwrap();
+        writer.write_all(bytes).unwrap();
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    fn read_all(input: String) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        dearmor(Cursor::new(input.into_bytes()))?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn armor_round_trip() {
+        for size in [0, 1, BYTES_PER_LINE, BYTES_PER_LINE * 3 + 7] {
+            let bytes = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+            let armored = armor(&bytes);
+            assert!(armored.starts_with(BEGIN));
+            assert!(armored.lines().all(|line| line.len() <= 64));
+            assert!(is_armored(format!("\n  {armored}").as_bytes()));
+            assert_eq!(read_all(format!("\n  {armored}")).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn crc24_matches_openpgp() {
+        // "123456789" is the standard check input for CRC-24/OPENPGP
+        assert_eq!(crc24(CRC24_INIT, b""), CRC24_INIT);
+        assert_eq!(crc24(CRC24_INIT, b"123456789"), 0x21CF02);
+    }
+
+    #[test]
+    fn damaged_armor_fails() {
+        let armored = armor(b"some encrypted bytes that get damaged");
+        let damaged = armored.replacen("c29t", "c29u", 1);
+        assert!(read_all(damaged).is_err());
+        let truncated = armored.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(read_all(truncated).is_err());
+    }
+
+    #[test]
+    fn binary_passes_through() {
+        let binary = vec![2u8, 0, 5, b'{', b'}'];
+        let mut decoded = Vec::new();
+        dearmor(Cursor::new(binary.clone()))
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, binary);
+        assert!(!is_armored(&binary));
+    }
+}
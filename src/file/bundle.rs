@@ -0,0 +1,218 @@
+//! Bundles pack a whole directory into a single tar archive so it can be encrypted as one file.
+//!
+//! The archive is a plain ustar file, so a bundle that's decrypted without '--extract' can still be unpacked with
+//! standard tools. Only regular files and directories are bundled, along with their relative paths and permission
+//! bits. Extraction rejects any entry whose path could land outside of the destination directory.
+
+use super::output::{OutputFile, Overwrite};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+use tar::{Archive, Builder, EntryType};
+
+/// Offset and value of the magic that identifies a ustar header.
+const USTAR_MAGIC_OFFSET: usize = 257;
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+/// One entry in a bundle's table of contents.
+pub struct BundleEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mode: u32,
+    pub is_dir: bool,
+}
+
+/// Check whether decrypted bytes look like a bundle.
+pub fn is_bundle(bytes: &[u8]) -> bool {
+    bytes
+        .get(USTAR_MAGIC_OFFSET..USTAR_MAGIC_OFFSET + USTAR_MAGIC.len())
+        .is_some_and(|magic| magic == USTAR_MAGIC)
+}
+
+/// Pack every file and directory beneath `dir` into an in-memory archive, with paths relative to `dir`. Symlinks and
+/// special files are skipped and returned so the caller can report them.
+pub fn pack(dir: &Path) -> Result<(Vec<u8>, Vec<PathBuf>), String> {
+    let mut builder = Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    let mut skipped = Vec::new();
+    pack_dir(&mut builder, dir, Path::new(""), &mut skipped)?;
+    let archive = builder
+        .into_inner()
+        .map_err(|e| format!("Couldn't finish bundle: {e}"))?;
+    Ok((archive, skipped))
+}
+
+fn pack_dir<W: Write>(
+    builder: &mut Builder<W>,
+    dir: &Path,
+    relative: &Path,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let read_err = |e: io::Error| format!("Couldn't read directory '{}': {e}", dir.display());
+    let mut entries = fs::read_dir(dir)
+        .map_err(read_err)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_err)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = relative.join(entry.file_name());
+        let file_type = entry.file_type().map_err(read_err)?;
+        let add_err = |e: io::Error| format!("Couldn't add '{}' to bundle: {e}", path.display());
+        if file_type.is_dir() {
+            builder.append_dir(&name, &path).map_err(add_err)?;
+            pack_dir(builder, &path, &name, skipped)?;
+        } else if file_type.is_file() {
+            builder
+                .append_path_with_name(&path, &name)
+                .map_err(add_err)?;
+        } else {
+            skipped.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// List the entries of a bundle without extracting it.
+pub fn list(bundle: &[u8]) -> Result<Vec<BundleEntry>, String> {
+    let read_err = |e: io::Error| format!("Couldn't read bundle: {e}");
+    let mut archive = Archive::new(bundle);
+    archive
+        .entries()
+        .map_err(read_err)?
+        .map(|entry| {
+            let entry = entry.map_err(read_err)?;
+            let header = entry.header();
+            Ok(BundleEntry {
+                path: entry.path().map_err(read_err)?.into_owned(),
+                size: header.size().map_err(read_err)?,
+                mode: header.mode().map_err(read_err)?,
+                is_dir: header.entry_type() == EntryType::Directory,
+            })
+        })
+        .collect()
+}
+
+/// Extract a bundle into `dest`, creating it if necessary. Existing files are never overwritten. Returns the number of
+/// files that were written.
+pub fn extract(bundle: &[u8], dest: &Path) -> Result<usize, String> {
+    let read_err = |e: io::Error| format!("Couldn't read bundle: {e}");
+    fs::create_dir_all(dest)
+        .map_err(|e| format!("Couldn't create directory '{}': {e}", dest.display()))?;
+    let mut archive = Archive::new(bundle);
+    let mut extracted = 0;
+    // directory permissions are applied last so that a read-only directory doesn't stop its contents being written
+    let mut dir_modes = Vec::new();
+    for entry in archive.entries().map_err(read_err)? {
+        let mut entry = entry.map_err(read_err)?;
+        let relative = entry.path().map_err(read_err)?.into_owned();
+        let target = dest.join(safe_relative_path(&relative)?);
+        let mode = entry.header().mode().map_err(read_err)?;
+        let write_err = |e: io::Error| format!("Couldn't extract '{}': {e}", target.display());
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                fs::create_dir_all(&target).map_err(write_err)?;
+                dir_modes.push((target, mode));
+            }
+            EntryType::Regular => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(write_err)?;
+                }
+                let mut output = OutputFile::create(&target, Overwrite::Never)?;
+                io::copy(&mut entry, &mut output).map_err(write_err)?;
+                output.finish()?;
+                set_mode(&target, mode).map_err(write_err)?;
+                extracted += 1;
+            }
+            other => {
+                return Err(format!(
+                    "Bundle entry '{}' has unsupported type {:?}.",
+                    relative.display(),
+                    other
+                ));
+            }
+        }
+    }
+    for (dir, mode) in dir_modes.into_iter().rev() {
+        set_mode(&dir, mode)
+            .map_err(|e| format!("Couldn't set permissions on '{}': {e}", dir.display()))?;
+    }
+    Ok(extracted)
+}
+
+/// Only allow plain relative paths, so that entries like '../x' or '/etc/x' can't escape the destination directory.
+fn safe_relative_path(path: &Path) -> Result<&Path, String> {
+    let is_safe = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if is_safe {
+        Ok(path)
+    } else {
+        Err(format!(
+            "Refusing to extract '{}' as it would be written outside of the destination directory.",
+            path.display()
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // setuid, setgid and sticky bits are deliberately dropped
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_list_extract_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested/empty")).unwrap();
+        fs::write(root.join("src/a.txt"), "a").unwrap();
+        fs::write(root.join("src/nested/b.txt"), "bb").unwrap();
+
+        let (bundle, skipped) = pack(&root.join("src")).unwrap();
+        assert!(is_bundle(&bundle));
+        assert!(skipped.is_empty());
+        let paths = list(&bundle)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, entry.size, entry.is_dir))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                (PathBuf::from("a.txt"), 1, false),
+                (PathBuf::from("nested"), 0, true),
+                (PathBuf::from("nested/b.txt"), 2, false),
+                (PathBuf::from("nested/empty"), 0, true),
+            ]
+        );
+
+        assert_eq!(extract(&bundle, &root.join("out")).unwrap(), 2);
+        assert_eq!(fs::read(root.join("out/nested/b.txt")).unwrap(), b"bb");
+        assert!(root.join("out/nested/empty").is_dir());
+        // extracting again must not clobber the existing files
+        assert!(extract(&bundle, &root.join("out")).is_err());
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        assert!(safe_relative_path(Path::new("a/b.txt")).is_ok());
+        assert!(safe_relative_path(Path::new("../b.txt")).is_err());
+        assert!(safe_relative_path(Path::new("a/../../b.txt")).is_err());
+        assert!(safe_relative_path(Path::new("/etc/passwd")).is_err());
+        assert!(safe_relative_path(Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! Optional compression of the plaintext before it's encrypted, added by 'file encrypt --compress'.
+//!
+//! Compressed plaintext starts with `MAGIC` and a byte naming the codec, followed by the compressed data. Compression
+//! wraps everything that's encrypted, including a metadata envelope, and since the codec is encrypted along with the
+//! data it isn't visible without decrypting.
+//!
+//! Decryption always checks for the header and decompresses, so compressed files need no extra flags to decrypt.
+//!
+//! To keep a small compression bomb from exhausting memory or disk, decompressed output may only be `MAX_RATIO` times
+//! the size of the compressed data it came from, on top of `MIN_OUTPUT_LIMIT`. Compression applies the same limit, so
+//! files that ironhide compressed can always be decompressed.
+
+use clap::ValueEnum;
+use flate2::{Compression, read::GzEncoder, write::GzDecoder};
+use std::{
+    io::{self, Cursor, Read, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+const MAGIC: &[u8] = b"IHCMPRS1";
+const HEADER_LEN: usize = MAGIC.len() + 1;
+/// How many times larger decompressed output may be than the compressed data it came from.
+const MAX_RATIO: u64 = 100;
+/// Output allowed on top of `MAX_RATIO`, so small files that compress very well aren't a problem.
+const MIN_OUTPUT_LIMIT: u64 = 64 * 1024 * 1024;
+/// How much of the plaintext is read to recognize content that's already compressed.
+const SNIFF_LEN: usize = 32;
+/// Signatures of formats that are already compressed, along with the offset they're found at.
+const COMPRESSED_SIGNATURES: &[(usize, &[u8])] = &[
+    (0, b"\x1f\x8b"),            // gzip
+    (0, b"\x28\xb5\x2f\xfd"),    // zstd
+    (0, b"BZh"),                 // bzip2
+    (0, b"\xfd7zXZ\x00"),        // xz
+    (0, b"\x04\x22\x4d\x18"),    // lz4
+    (0, b"PK\x03\x04"),          // zip, and formats built on it like docx and jar
+    (0, b"7z\xbc\xaf\x27\x1c"),  // 7-Zip
+    (0, b"Rar!\x1a\x07"),        // RAR
+    (0, b"\x89PNG\r\n\x1a\n"),   // PNG
+    (0, b"\xff\xd8\xff"),        // JPEG
+    (0, b"GIF8"),                // GIF
+    (8, b"WEBP"),                // WebP
+    (4, b"ftyp"),                // MP4, MOV and HEIC
+    (0, b"\x1a\x45\xdf\xa3"),    // Matroska and WebM
+    (0, b"ID3"),                 // MP3
+    (0, b"OggS"),                // Ogg
+    (0, b"fLaC"),                // FLAC
+    (0, b"IHSTREAM"),            // ironhide stream, which is encrypted
+    (0, b"IHUNMNGD"),            // ironhide unmanaged file, which is encrypted
+    (0, b"-----BEGIN IRONHIDE"), // ironhide armor, which is encrypted
+];
+
+/// Compression algorithm used for the plaintext.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Zstd => 1,
+            Codec::Gzip => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Zstd),
+            2 => Some(Codec::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Most decompressed output allowed from `compressed` bytes of compressed data.
+fn output_limit(compressed: u64) -> u64 {
+    MIN_OUTPUT_LIMIT.saturating_add(compressed.saturating_mul(MAX_RATIO))
+}
+
+/// Compress everything readable from `reader` with `codec`, prefixed with the header naming the codec. Reading fails
+/// if the data compresses too well to be decompressed again within the output limit.
+pub fn compress<'a>(codec: Codec, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    let header = Cursor::new([MAGIC, &[codec.id()]].concat());
+    let consumed = Arc::new(AtomicU64::new(0));
+    let reader = CountingReader {
+        inner: reader,
+        count: consumed.clone(),
+    };
+    let encoder: Box<dyn Read + 'a> = match codec {
+        Codec::Zstd => Box::new(zstd::stream::read::Encoder::new(reader, 0)?),
+        Codec::Gzip => Box::new(GzEncoder::new(reader, Compression::default())),
+    };
+    Ok(Box::new(header.chain(LimitedEncoder {
+        inner: encoder,
+        consumed,
+        produced: 0,
+    })))
+}
+
+/// Reader that counts the bytes read through it, for `LimitedEncoder` to check.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Compressed output that fails once more data has been compressed than `output_limit` allows for what was produced.
+/// Output lags the data it came from, so when decompressing the limit is never hit before it is here.
+struct LimitedEncoder<'a> {
+    inner: Box<dyn Read + 'a>,
+    consumed: Arc<AtomicU64>,
+    produced: u64,
+}
+
+impl Read for LimitedEncoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.produced += read as u64;
+        if self.consumed.load(Ordering::Relaxed) > output_limit(self.produced) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the data compresses more than {MAX_RATIO} times, so it couldn't be safely decompressed. Encrypt \
+                     it without '--compress'"
+                ),
+            ));
+        }
+        Ok(read)
+    }
+}
+
+/// Check whether the contents of `reader` look like they're already compressed, e.g. because they're an archive or
+/// an image. Returns a reader that still yields every byte of `reader`.
+pub fn sniff<'a>(mut reader: impl Read + 'a) -> io::Result<(bool, impl Read + 'a)> {
+    let mut start = Vec::with_capacity(SNIFF_LEN);
+    (&mut reader)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut start)?;
+    Ok((is_compressed(&start), Cursor::new(start).chain(reader)))
+}
+
+fn is_compressed(start: &[u8]) -> bool {
+    COMPRESSED_SIGNATURES.iter().any(|(offset, signature)| {
+        start
+            .get(*offset..)
+            .is_some_and(|s| s.starts_with(signature))
+    })
+}
+
+/// Writer that decompresses decrypted plaintext, if it was compressed, and passes the result through to `inner`.
+/// Plaintext that wasn't compressed is passed through unchanged.
+pub struct Decompressor<W: Write> {
+    /// Only `None` after a write failed part way through.
+    state: Option<State<W>>,
+}
+
+enum State<W: Write> {
+    /// Bytes held back until it's known whether they start with a compression header.
+    Pending(W, Vec<u8>),
+    Plain(W),
+    Zstd(zstd::stream::write::Decoder<'static, LimitedWriter<W>>),
+    Gzip(GzDecoder<LimitedWriter<W>>),
+}
+
+impl<W: Write> Decompressor<W> {
+    pub fn new(inner: W) -> Self {
+        Decompressor {
+            state: Some(State::Pending(inner, Vec::new())),
+        }
+    }
+
+    /// Finish decompressing, returning the inner writer along with the codec the plaintext was compressed with, if it
+    /// was.
+    pub fn finish(self) -> Result<(W, Option<Codec>), String> {
+        let decompress_err = |e: io::Error| format!("Failed to decompress decrypted document: {e}");
+        match self
+            .state
+            .ok_or("Failed to decompress decrypted document.")?
+        {
+            State::Pending(mut inner, pending) => {
+                if pending.starts_with(MAGIC) {
+                    return Err("Decrypted file has a truncated compression header.".to_string());
+                }
+                inner
+                    .write_all(&pending)
+                    .map_err(|e| format!("Failed to write decrypted document: {e}"))?;
+                Ok((inner, None))
+            }
+            State::Plain(inner) => Ok((inner, None)),
+            State::Zstd(mut decoder) => {
+                decoder.flush().map_err(decompress_err)?;
+                Ok((decoder.into_inner().inner, Some(Codec::Zstd)))
+            }
+            State::Gzip(decoder) => Ok((
+                decoder.finish().map_err(decompress_err)?.inner,
+                Some(Codec::Gzip),
+            )),
+        }
+    }
+
+    /// Decide what to do with the pending bytes once there are enough of them to know whether they're compressed.
+    fn start(inner: W, pending: Vec<u8>) -> io::Result<State<W>> {
+        let prefix_len = pending.len().min(MAGIC.len());
+        if pending[..prefix_len] != MAGIC[..prefix_len] {
+            let mut inner = inner;
+            inner.write_all(&pending)?;
+            return Ok(State::Plain(inner));
+        }
+        if pending.len() < HEADER_LEN {
+            return Ok(State::Pending(inner, pending));
+        }
+        let inner = LimitedWriter {
+            inner,
+            written: 0,
+            limit: output_limit(0),
+        };
+        let mut state = match Codec::from_id(pending[MAGIC.len()]) {
+            Some(Codec::Zstd) => State::Zstd(zstd::stream::write::Decoder::new(inner)?),
+            Some(Codec::Gzip) => State::Gzip(GzDecoder::new(inner)),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decrypted file was compressed with an unknown codec",
+                ));
+            }
+        };
+        state.write_all(&pending[HEADER_LEN..])?;
+        Ok(state)
+    }
+}
+
+impl<W: Write> State<W> {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            State::Pending(..) => unreachable!("pending bytes are only written once started"),
+            State::Plain(inner) => inner.write_all(buf),
+            State::Zstd(decoder) => {
+                decoder.get_mut().allow_more(buf.len());
+                decoder.write_all(buf)
+            }
+            State::Gzip(decoder) => {
+                decoder.get_mut().allow_more(buf.len());
+                decoder.write_all(buf)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Decompressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = self
+            .state
+            .take()
+            .ok_or_else(|| io::Error::other("an earlier write failed"))?;
+        self.state = Some(match state {
+            State::Pending(inner, mut pending) => {
+                pending.extend_from_slice(buf);
+                Self::start(inner, pending)?
+            }
+            mut state => {
+                state.write_all(buf)?;
+                state
+            }
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            Some(State::Pending(inner, _) | State::Plain(inner)) => inner.flush(),
+            Some(State::Zstd(decoder)) => decoder.flush(),
+            Some(State::Gzip(decoder)) => decoder.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writer for decompressed output that fails once more has been written than `output_limit` allows for the compressed
+/// data seen so far.
+struct LimitedWriter<W> {
+    inner: W,
+    written: u64,
+    limit: u64,
+}
+
+impl<W> LimitedWriter<W> {
+    /// Raise the limit for `compressed` more bytes of compressed data.
+    fn allow_more(&mut self, compressed: usize) {
+        self.limit = self
+            .limit
+            .saturating_add((compressed as u64).saturating_mul(MAX_RATIO));
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "decompressed data is more than {MAX_RATIO} times the size of the compressed data, so it was \
+                     rejected as a possible compression bomb"
+                ),
+            ));
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompress_in_chunks(plaintext: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+        let mut decompressor = Decompressor::new(Vec::new());
+        for chunk in plaintext.chunks(chunk_size) {
+            decompressor.write_all(chunk).map_err(|e| e.to_string())?;
+        }
+        decompressor.finish().map(|(decompressed, _)| decompressed)
+    }
+
+    #[test]
+    fn round_trips_every_codec() {
+        let contents = br#"{"level":"info","message":"hello"}"#.repeat(100);
+        for codec in [Codec::Zstd, Codec::Gzip] {
+            let mut compressed = Vec::new();
+            compress(codec, &contents[..])
+                .unwrap()
+                .read_to_end(&mut compressed)
+                .unwrap();
+            assert!(compressed.starts_with(MAGIC));
+            assert!(compressed.len() < contents.len() / 10);
+            for chunk_size in [1, 5, 4096] {
+                assert_eq!(
+                    decompress_in_chunks(&compressed, chunk_size).unwrap(),
+                    contents
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn limits_how_much_is_decompressed() {
+        let zeros = || io::repeat(0).take(MIN_OUTPUT_LIMIT * 2);
+        // compressing data that would be rejected fails rather than writing something that can't be decompressed
+        let mut compressed = Vec::new();
+        assert!(
+            compress(Codec::Zstd, zeros())
+                .unwrap()
+                .read_to_end(&mut compressed)
+                .is_err()
+        );
+
+        // a bomb compressed by something else is rejected partway through
+        let mut bomb = [MAGIC, &[Codec::Zstd.id()]].concat();
+        zstd::stream::copy_encode(zeros(), &mut bomb, 0).unwrap();
+        let mut decompressor = Decompressor::new(io::sink());
+        let decompressed = decompressor
+            .write_all(&bomb)
+            .map_err(|e| e.to_string())
+            .and_then(|_| decompressor.finish().map(|_| ()));
+        assert!(decompressed.unwrap_err().contains("compression bomb"));
+    }
+
+    #[test]
+    fn passes_through_uncompressed() {
+        for contents in [&b""[..], b"IH", b"plain text", b"IHCMPRS", b"IHENVLP1{}"] {
+            assert_eq!(decompress_in_chunks(contents, 3).unwrap(), contents);
+        }
+        assert!(decompress_in_chunks(b"IHCMPRS1", 3).is_err());
+        assert!(decompress_in_chunks(b"IHCMPRS1\x09data", 3).is_err());
+    }
+
+    #[test]
+    fn recognizes_compressed_content() {
+        let (compressed, mut reader) = sniff(&b"\x1f\x8b\x08rest of the file"[..]).unwrap();
+        assert!(compressed);
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"\x1f\x8b\x08rest of the file");
+        assert!(is_compressed(b"\x00\x00\x00\x18ftypmp42"));
+        assert!(is_compressed(b"-----BEGIN IRONHIDE MESSAGE-----"));
+        assert!(!is_compressed(b"{\"json\": true}"));
+        assert!(!is_compressed(b""));
+    }
+}
@@ -1,11 +1,25 @@
-use crate::util::{self, act_on_all_files};
+use super::{
+    armor, bundle,
+    compress::{Codec, Decompressor},
+    envelope::{Envelope, Unwrapper},
+    naming::NamingArgs,
+    output::{self, OutputFile, Overwrite, OverwriteArgs},
+    stream::{self, StreamDecryptor},
+    unmanaged,
+};
+use crate::{
+    filter::FilterArgs,
+    jobs::JobsArgs,
+    util::{self, act_on_all_files},
+};
 use clap::Parser;
 use ironoxide::prelude::BlockingIronOxide;
 use std::{
-    fs::{self, File, OpenOptions},
+    collections::{HashMap, HashSet},
+    fs::{self, File},
     io::{self, Read, Write},
-    path::Path,
-    path::PathBuf,
+    path::{MAIN_SEPARATOR, Path, PathBuf},
+    sync::Mutex,
 };
 use yansi::Paint;
 
@@ -21,24 +35,50 @@ const EXAMPLES: &str = "EXAMPLES
     path/to/file.json.iron file.
         $ ironhide file decrypt path/to/file.json.iron -d -o file.json
 
-    Decrypt all of the '.iron' files and write them to files without the '.iron' extension.
-        $ ironhide file decrypt *.iron
+    Decrypt all of the '.iron' files and write them to files without the '.iron' extension, leaving any that have
+    already been decrypted alone.
+        $ ironhide file decrypt *.iron --skip-existing
+
+    Decrypt all of the '.enc' files written by 'file encrypt --suffix .enc' under 'encrypted/'.
+        $ ironhide file decrypt -r encrypted/ --suffix .enc
+
+    Decrypt all of the '.iron' files into 'decrypted/', creating it if needed.
+        $ ironhide file decrypt *.iron -o decrypted/ --mkdir
+
+    Decrypt every '.iron' file under 'encrypted/' and write the results into 'config/', preserving the directory
+    structure.
+        $ ironhide file decrypt -r encrypted/ -o config/
+
+    Decrypt a bundle created with 'file encrypt --bundle' and unpack its files into 'config/'.
+        $ ironhide file decrypt config.iron --extract config/
 
     Decrypt the provided file and write the decrypted bytes to stdout.
         $ ironhide file decrypt path/to/file.iron -o -
 
     Decrypt the provided file from stdin and write the decrypted bytes to stdout.
         $  cat encryptedfile.iron | ironhide file decrypt -s -o -
+
+    Decrypt a file encrypted with 'file encrypt --preserve', restoring its original name, permissions and
+    modification time.
+        $ ironhide file decrypt path/to/renamed.iron --preserve
+
+    Decrypt an ASCII-armored message pasted into the terminal. Armored input is detected automatically.
+        $ ironhide file decrypt -s -o -
 ";
 
 #[derive(Parser)]
 #[clap(after_help = EXAMPLES)]
 /// Decrypt a file or list of files. By default, the decrypted file is written to the same directory without the
-/// '.iron' extension.
+/// '.iron' extension. Files without it are written with the name stored in the document, or with '.decrypted' added if
+/// there isn't one.
 pub struct Decrypt {
     /// Delete the encrypted source file(s) after successful encryption.
     #[clap(short, long, num_args = 0)]
     delete: bool,
+    /// Unpack a bundle created with 'file encrypt --bundle' into the given directory instead of writing out the
+    /// archive. The directory is created if needed and existing files are never overwritten.
+    #[clap(value_parser = clap::value_parser!(PathBuf), long, conflicts_with_all = ["out", "recursive", "stdin", "force", "skip_existing", "backup"])]
+    extract: Option<PathBuf>,
     /// Path of file or files to decrypt.
     #[clap(value_parser = clap::value_parser!(PathBuf), num_args = 1.., required = true)]
     files: Vec<PathBuf>,
@@ -46,15 +86,35 @@ pub struct Decrypt {
     /// '~/.iron' directory.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
     keyfile: Option<PathBuf>,
-    /// Filename where decrypted file will be written. Only allowed if a single file is
-    /// being decrypted.
-    /// Use '-o -' to write decrypted file content to stdout, but fair warning, the output is binary and not ASCII.
+    /// Create the output directory given with '-o' if it doesn't already exist when decrypting into a directory.
+    #[clap(long, num_args = 0, requires = "out")]
+    mkdir: bool,
+    /// Filename where decrypted file will be written, or a directory to write the decrypted file(s) into. Has to be a
+    /// directory when decrypting multiple files, and with '-r' the results are written to that directory with the
+    /// same structure as the input. Use '-o -' to write decrypted file content to stdout, but fair warning, the output is binary and not ASCII.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
     out: Option<PathBuf>,
+    /// Restore the permissions and modification time stored by 'file encrypt --preserve'. Unless '-o' names the
+    /// file or '--output-template' is used, the file is also written with its original name. Files encrypted without
+    /// '--preserve' are decrypted as usual.
+    #[clap(long, num_args = 0, conflicts_with = "extract")]
+    preserve: bool,
+    /// Decrypt every file ending with the '--suffix', '.iron' by default, in the provided directories and their subdirectories. When used with '-o', the
+    /// results are written to that directory with the same structure as the input.
+    #[clap(short, long, num_args = 0, conflicts_with = "stdin")]
+    recursive: bool,
     /// Read data to decrypt from stdin. If used, no source files should be provided as
     /// arguments and you must use the '-o' flag.
     #[clap(short, long, num_args = 0, conflicts_with = "files", requires = "out")]
     stdin: bool,
+    #[clap(flatten)]
+    overwrite: OverwriteArgs,
+    #[clap(flatten)]
+    naming: NamingArgs,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
 }
 
 impl util::GetKeyfile for Decrypt {
@@ -67,62 +127,150 @@ pub fn decrypt_files(
     sdk: &BlockingIronOxide,
     Decrypt {
         delete,
+        extract,
         files,
+        mkdir,
         out,
+        preserve,
+        recursive,
         stdin,
+        overwrite,
+        naming,
+        filter,
+        jobs,
         ..
     }: Decrypt,
 ) -> Result<(), String> {
+    let overwrite = overwrite.policy();
+    let options = OutputOptions {
+        overwrite,
+        preserve,
+        delete,
+    };
     if stdin {
-        let mut encrypted_document: Vec<u8> = vec![];
-        match io::stdin().read_to_end(&mut encrypted_document) {
-            Ok(_) => {
-                // safe to unwrap here because `stdin` always has to have `out` set.
-                let out_path = out.unwrap();
-                decrypt_file(sdk, encrypted_document, None, out_path, delete)?;
+        // safe to unwrap here because `stdin` always has to have `out` set.
+        let out_path = out.unwrap();
+        if output::should_skip(&out_path, overwrite) {
+            return Ok(());
+        }
+        match read_encrypted_input(io::stdin().lock()) {
+            Ok(encrypted_input) => {
+                decrypt_to_output(sdk, encrypted_input, None, out_path, None, options)?;
             }
             Err(e) => util::println_paint(Paint::red(format!("Error reading stdin: {}", e))),
         }
-    } else if out.is_some() && files.len() > 1 {
+    } else if let Some(extract_dir) = extract {
+        let files = util::collect_files(&files, false, &filter, |_| true)?;
+        act_on_all_files(
+            &files,
+            jobs.count(),
+            |path: &PathBuf| -> Result<(), String> {
+                let decrypted = decrypt_to_memory(sdk, path)?;
+                if !bundle::is_bundle(&decrypted) {
+                    return Err(format!(
+                        "'{}' isn't a bundle. Decrypt it without '--extract' instead.",
+                        path.display()
+                    ));
+                }
+                let extracted = bundle::extract(&decrypted, &extract_dir)?;
+                util::println_paint(Paint::green(format!(
+                    "Extracted {extracted} file(s) from '{}' into {}",
+                    path.display(),
+                    extract_dir.display()
+                )));
+                delete_source(Some(path), delete);
+                Ok(())
+            },
+            "extracted",
+        )
+        .map_err(|(e, _)| e)?;
+    } else if files.len() > 1 && out.as_deref().is_some_and(output::is_stdout) {
         util::println_paint(Paint::red(
-            "Cannot use '-o' flag with multiple files.".to_string(),
+            "Cannot use '-o -' with multiple files. Provide an output directory instead."
+                .to_string(),
+        ));
+    } else if recursive && out == Some(PathBuf::from("-")) {
+        util::println_paint(Paint::red(
+            "Cannot use '-o -' with '-r'. Provide an output directory instead.".to_string(),
         ));
     } else {
+        let all_files =
+            util::collect_files(&files, recursive, &filter, |path| naming.has_suffix(path))?;
+        // a single file can be decrypted into a directory too, which a trailing separator marks as one
+        let into_dir = !recursive
+            && out.as_deref().is_some_and(|out| {
+                files.len() > 1 || out.is_dir() || out.to_string_lossy().ends_with(MAIN_SEPARATOR)
+            });
+        if let Some(out_dir) = &out
+            && into_dir
+        {
+            util::prepare_output_dir(out_dir, mkdir)?;
+        }
+        // inputs without the suffix are planned under their fallback name here, and only look up the name stored with
+        // their document once they're being decrypted
+        let out_paths = all_files
+            .iter()
+            .map(|path| {
+                let name = || naming.decrypted_name(path, || None);
+                match &out {
+                    Some(out_dir) if recursive => Ok(util::mirror_output_path(
+                        out_dir, path, &files,
+                    )?
+                    .with_file_name(name()?)),
+                    Some(out_dir) if into_dir => Ok(out_dir.join(name()?)),
+                    Some(out) => Ok(out.clone()),
+                    None => Ok(path.with_file_name(name()?)),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        util::check_output_collisions(&all_files, &out_paths)?;
+        let claimed_paths = Mutex::new(out_paths.iter().cloned().collect::<HashSet<_>>());
+        let out_paths = all_files.iter().zip(out_paths).collect::<HashMap<_, _>>();
+        // the original name is only used when the output's file name wasn't chosen with '-o' or a template
+        let rename = (out.is_none() || into_dir || recursive) && !naming.has_template();
         act_on_all_files(
-            &files,
+            &all_files,
+            jobs.count(),
             |path: &PathBuf| -> Result<(), String> {
-                let mut file = File::open(path).map_err(|e| {
+                let mut out_path = out_paths[path].clone();
+                if rename && !naming.has_suffix(path) {
+                    let stored_path = out_path.with_file_name(
+                        naming.decrypted_name(path, || stored_document_name(sdk, path))?,
+                    );
+                    if stored_path != out_path {
+                        claim_output_path(&claimed_paths, path, &stored_path)?;
+                        out_path = stored_path;
+                    }
+                }
+                if output::should_skip(&out_path, overwrite) {
+                    return Ok(());
+                }
+                let file = File::open(path).map_err(|e| {
                     format!(
                         "Provided path '{}' doesn't exist or is not readable: {e}",
                         path.display()
                     )
                 })?;
-                let mut encrypted_document = Vec::new();
-                file.read_to_end(&mut encrypted_document).map_err(|e| {
+                let encrypted_input = read_encrypted_input(file).map_err(|e| {
                     format!(
                         "Failed to read bytes from the encrypted document at {}: {e}",
                         path.display()
                     )
                 })?;
-                let in_parent = path.parent().ok_or(format!(
-                    "Failed to find parent of input path {}.",
-                    path.display()
-                ))?;
-                let out_path =
-                    out.clone()
-                        .unwrap_or(in_parent.join(Path::new(path.file_stem().ok_or(format!(
-                            "Failed to extract default output file name from input path {}.",
-                            path.display()
-                        ))?)));
-                decrypt_file(
+                let claimed_paths = rename.then_some(&claimed_paths);
+                let Some(out_path) = decrypt_to_output(
                     sdk,
-                    encrypted_document,
+                    encrypted_input,
                     Some(path),
-                    out_path.clone(),
-                    delete,
-                )?;
-                if files.len() == 1 {
-                    let out_logged_path = get_output_logged_path(out_path)?;
+                    out_path,
+                    claimed_paths,
+                    options,
+                )?
+                else {
+                    return Ok(());
+                };
+                if all_files.len() == 1 || recursive {
+                    let out_logged_path = get_output_logged_path(&out_path);
                     util::println_paint(Paint::green(format!(
                         "File successfully decrypted and written to {}",
                         out_logged_path
@@ -138,52 +286,180 @@ pub fn decrypt_files(
     Ok(())
 }
 
-fn get_output_writer(out_path: PathBuf) -> Result<Box<dyn Write>, String> {
-    let out_writer: Box<dyn Write> = if out_path == PathBuf::from("-") {
-        Box::new(io::stdout())
+/// Encrypted input after removing any armor and checking whether it's a stream container. Streams are left unread so
+/// they can be decrypted a segment at a time, while regular documents are read fully into memory.
+enum EncryptedInput<R: Read> {
+    Document(Vec<u8>),
+    Stream(R),
+}
+
+fn read_encrypted_input<R: Read + 'static>(reader: R) -> io::Result<EncryptedInput<impl Read>> {
+    let (is_stream, mut reader) = stream::sniff(armor::dearmor(reader)?)?;
+    if is_stream {
+        Ok(EncryptedInput::Stream(reader))
     } else {
-        OpenOptions::new()
-            .create_new(true)
-            .append(true)
-            .open(out_path.clone())
-            .map_err(|e| {
-                format!(
-                    "Couldn't create a file at the desired output path '{}': {e}",
-                    out_path.display()
-                )
-            })
-            .map(Box::new)?
-    };
-    Ok(out_writer)
+        let mut encrypted_document = Vec::new();
+        reader.read_to_end(&mut encrypted_document)?;
+        Ok(EncryptedInput::Document(encrypted_document))
+    }
+}
+
+/// Decrypt the file at `path` entirely in memory, whether it holds a single document or a stream.
+pub fn decrypt_to_memory(sdk: &BlockingIronOxide, path: &Path) -> Result<Vec<u8>, String> {
+    let mut decrypted = Vec::new();
+    decrypt_to(sdk, path, &mut decrypted)?;
+    Ok(decrypted)
+}
+
+/// What was found wrapped around the contents of a decrypted file.
+pub struct Decrypted {
+    /// Metadata envelope stored by 'file encrypt --preserve'.
+    pub envelope: Option<Envelope>,
+    /// Codec the plaintext was compressed with by 'file encrypt --compress'.
+    pub codec: Option<Codec>,
+}
+
+/// Decrypt the file at `path` into `writer`, returning the metadata envelope and compression codec if it has them.
+/// Streams are decrypted a segment at a time, so they aren't held in memory.
+pub fn decrypt_to(
+    sdk: &BlockingIronOxide,
+    path: &Path,
+    writer: &mut dyn Write,
+) -> Result<Decrypted, String> {
+    let file = File::open(path).map_err(|e| {
+        format!(
+            "Provided path '{}' doesn't exist or is not readable: {e}",
+            path.display()
+        )
+    })?;
+    let encrypted_input = read_encrypted_input(file).map_err(|e| {
+        format!(
+            "Failed to read bytes from the encrypted document at {}: {e}",
+            path.display()
+        )
+    })?;
+    decrypt_input(sdk, encrypted_input, writer)
+}
+
+/// Decrypt `encrypted_input` into `writer`, decompressing it if it was compressed and removing the metadata envelope if
+/// there is one and checking the contents against it.
+fn decrypt_input(
+    sdk: &BlockingIronOxide,
+    encrypted_input: EncryptedInput<impl Read>,
+    writer: &mut dyn Write,
+) -> Result<Decrypted, String> {
+    let mut decompressor = Decompressor::new(Unwrapper::new(writer));
+    match encrypted_input {
+        EncryptedInput::Document(encrypted_document) => decompressor
+            .write_all(&decrypt_document(sdk, &encrypted_document)?)
+            .map_err(|e| format!("Failed to write decrypted document: {e}"))?,
+        EncryptedInput::Stream(reader) => {
+            StreamDecryptor::new(sdk, reader)?.decrypt_to(&mut decompressor)?;
+        }
+    }
+    let (unwrapper, codec) = decompressor.finish()?;
+    let (_, envelope) = unwrapper.finish()?;
+    Ok(Decrypted { envelope, codec })
 }
 
-fn get_output_logged_path(out_path: PathBuf) -> Result<String, String> {
-    let out_logged_path = if out_path == PathBuf::from("-") {
-        "stdout"
+/// Decrypt a document that was read fully into memory, which is either a managed document or an unmanaged container.
+fn decrypt_document(sdk: &BlockingIronOxide, encrypted_document: &[u8]) -> Result<Vec<u8>, String> {
+    if unmanaged::is_unmanaged(encrypted_document) {
+        unmanaged::decrypt(sdk, encrypted_document)
     } else {
-        out_path.to_str().ok_or(format!(
-            "Output path '{}' wasn't valid unicode.",
+        sdk.document_decrypt(encrypted_document)
+            .map(|decrypt_result| decrypt_result.decrypted_data().to_vec())
+            .map_err(|e| format!("Failed to decrypt encrypted document: {e}"))
+    }
+}
+
+/// Reserve `out_path` for the output of `input`, failing if another input's output or `input` itself is already there.
+fn claim_output_path(
+    claimed_paths: &Mutex<HashSet<PathBuf>>,
+    input: &Path,
+    out_path: &Path,
+) -> Result<(), String> {
+    let mut claimed_paths = claimed_paths.lock().unwrap_or_else(|e| e.into_inner());
+    if out_path == input || !claimed_paths.insert(out_path.to_path_buf()) {
+        Err(format!(
+            "'{}' would be decrypted to '{}' using the name stored with its document, which is already taken.",
+            input.display(),
             out_path.display()
-        ))?
-    };
-    Ok(out_logged_path.to_string())
+        ))
+    } else {
+        Ok(())
+    }
 }
 
-fn decrypt_file(
+/// Name stored with the document at `path`, which is used to name its output when the file name doesn't show it.
+fn stored_document_name(sdk: &BlockingIronOxide, path: &Path) -> Option<String> {
+    let document_id = util::get_document_id(sdk, path).ok()?;
+    let metadata = sdk.document_get_metadata(&document_id).ok()?;
+    metadata.name().map(|name| name.name().to_string())
+}
+
+fn get_output_logged_path(out_path: &Path) -> String {
+    if output::is_stdout(out_path) {
+        "stdout".to_string()
+    } else {
+        out_path.display().to_string()
+    }
+}
+
+/// How each decrypted output is written.
+#[derive(Clone, Copy)]
+struct OutputOptions {
+    overwrite: Overwrite,
+    preserve: bool,
+    delete: bool,
+}
+
+/// Decrypt `encrypted_input` and write it to `out_path`. With '--preserve' the envelope's metadata is restored to the
+/// output, which is also given its original name when `claimed_paths` is set, as long as that name isn't the input or
+/// another input's output. Returns where the output was written, or `None` if it was skipped because a file with the
+/// original name already exists.
+fn decrypt_to_output(
     sdk: &BlockingIronOxide,
-    encrypted_document: Vec<u8>,
+    encrypted_input: EncryptedInput<impl Read>,
     input_path: Option<&PathBuf>,
-    out_path: PathBuf,
-    delete: bool,
-) -> Result<(), String> {
-    let decrypt_result = sdk
-        .document_decrypt(&encrypted_document)
-        .map_err(|e| format!("Failed to decrypt encrypted document: {e}"))?;
-    let decrypted_document = decrypt_result.decrypted_data();
-    let mut decrypted_writer = get_output_writer(out_path)?;
-    decrypted_writer
-        .write_all(decrypted_document)
-        .map_err(|e| format!("Failed to write decrypted document: {e}"))?;
+    mut out_path: PathBuf,
+    claimed_paths: Option<&Mutex<HashSet<PathBuf>>>,
+    options: OutputOptions,
+) -> Result<Option<PathBuf>, String> {
+    let mut decrypted_writer = OutputFile::create(&out_path, options.overwrite)?;
+    let Decrypted { envelope, .. } = decrypt_input(sdk, encrypted_input, &mut decrypted_writer)?;
+    let envelope = envelope.filter(|_| options.preserve);
+    if let Some(name) = envelope.as_ref().and_then(Envelope::safe_name)
+        && let Some(claimed_paths) = claimed_paths
+        && let Some(input_path) = input_path
+        && !output::is_stdout(&out_path)
+    {
+        let original_path = out_path.with_file_name(name);
+        if original_path != out_path {
+            claim_output_path(claimed_paths, input_path, &original_path)?;
+            if output::should_skip(&original_path, options.overwrite) {
+                return Ok(None);
+            }
+            decrypted_writer.set_path(original_path.clone());
+            out_path = original_path;
+        }
+    }
+    decrypted_writer.finish()?;
+    if let Some(envelope) = &envelope
+        && !output::is_stdout(&out_path)
+    {
+        envelope.restore(&out_path).map_err(|e| {
+            format!(
+                "Couldn't restore the original permissions and modification time of '{}': {e}",
+                out_path.display()
+            )
+        })?;
+    }
+    delete_source(input_path, options.delete);
+    Ok(Some(out_path))
+}
+
+fn delete_source(input_path: Option<&PathBuf>, delete: bool) {
     if delete {
         match input_path {
             Some(infile) => {
@@ -199,5 +475,4 @@ fn decrypt_file(
             )),
         }
     }
-    Ok(())
 }
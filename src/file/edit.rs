@@ -0,0 +1,196 @@
+use super::{decrypt, update};
+use crate::util;
+use clap::Parser;
+use ironoxide::prelude::BlockingIronOxide;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tempfile::TempPath;
+use yansi::Paint;
+
+const EXAMPLES: &str = "EXAMPLES
+
+    $ ironhide file edit secrets.json.iron
+    $ EDITOR=nano ironhide file edit path/to/file.iron
+
+";
+
+/// Memory-backed directory preferred for the decrypted copy, so the plaintext never touches a disk.
+const SHARED_MEMORY_DIR: &str = "/dev/shm";
+const DEFAULT_EDITOR: &str = "vi";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Decrypt a file into a private temporary file, open it in your editor, and re-encrypt any changes when the editor
+/// exits. The file keeps its document ID, everyone it has been shared with and any metadata stored with '--preserve'.
+/// The editor is taken from '$VISUAL' or '$EDITOR'. The temporary file is wiped afterwards, even if the editor fails.
+pub struct Edit {
+    /// Path of the encrypted file to edit.
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    file: PathBuf,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+}
+
+impl util::GetKeyfile for Edit {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+pub fn edit_file(sdk: &BlockingIronOxide, Edit { file, .. }: Edit) -> Result<(), String> {
+    let document_id = match util::get_document_id(sdk, &file) {
+        Ok(document_id) => document_id,
+        Err(err) => {
+            util::println_paint(err);
+            return Ok(());
+        }
+    };
+    let mut original = Vec::new();
+    let decrypted = decrypt::decrypt_to(sdk, &file, &mut original)?;
+    let temp = PlaintextFile::create(&file, &original)?;
+    run_editor(temp.path())?;
+    let edited =
+        fs::read(temp.path()).map_err(|e| format!("Couldn't read the edited file: {e}"))?;
+    if edited == original {
+        util::println_paint(Paint::yellow(format!(
+            "No changes made, '{}' was not updated.",
+            file.display()
+        )));
+    } else {
+        // a metadata envelope is kept, describing the edited contents, and so is compression
+        let header = decrypted
+            .envelope
+            .map(|envelope| envelope.for_new_contents(&edited).to_header())
+            .unwrap_or_default();
+        update::update_encrypted_file(
+            sdk,
+            &document_id,
+            &file,
+            decrypted.codec,
+            &mut header.chain(&edited[..]),
+        )?;
+        util::println_paint(Paint::green(format!(
+            "Encrypted file '{}' successfully updated.",
+            file.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Decrypted copy of the file being edited. The file is only readable by the current user, and is overwritten and
+/// removed when this is dropped, whether or not editing succeeded.
+struct PlaintextFile {
+    path: TempPath,
+}
+
+impl PlaintextFile {
+    fn create(encrypted: &Path, contents: &[u8]) -> Result<Self, String> {
+        let dir = if Path::new(SHARED_MEMORY_DIR).is_dir() {
+            PathBuf::from(SHARED_MEMORY_DIR)
+        } else {
+            env::temp_dir()
+        };
+        // keep the plaintext's extension so the editor can pick the right syntax highlighting
+        let suffix = encrypted
+            .file_stem()
+            .map(Path::new)
+            .and_then(Path::extension)
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        // tempfile creates files with 0600 permissions on unix
+        let temp = tempfile::Builder::new()
+            .prefix("ironhide-edit-")
+            .suffix(&suffix)
+            .tempfile_in(&dir)
+            .map_err(|e| {
+                format!(
+                    "Couldn't create a temporary file in '{}': {e}",
+                    dir.display()
+                )
+            })?;
+        let path = temp.path().to_path_buf();
+        let plaintext = PlaintextFile {
+            path: temp.into_temp_path(),
+        };
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents))
+            .map_err(|e| format!("Couldn't write the temporary file: {e}"))?;
+        Ok(plaintext)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PlaintextFile {
+    fn drop(&mut self) {
+        // editors that save by replacing the file leave the original inode to the filesystem, so this is best effort
+        if util::overwrite_with_zeros(&self.path).is_err() {
+            util::println_paint(Paint::yellow(format!(
+                "Unable to wipe the temporary file '{}' before removing it.",
+                self.path.display()
+            )));
+        }
+        // `TempPath` removes the file when it's dropped
+    }
+}
+
+/// Run the user's editor on `path` and wait for it to exit. Ctrl-C is left to the editor while it runs, and SIGTERM or
+/// SIGHUP, e.g. from closing the terminal, are passed on to it, so that this process stays alive to wipe the plaintext
+/// afterwards.
+fn run_editor(path: &Path) -> Result<(), String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // allow editors configured with arguments, e.g. 'code --wait'
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let exit = util::run_in_foreground(Command::new(program).args(words).arg(path))
+        .map_err(|e| format!("Couldn't launch editor '{editor}': {e}"))?;
+    let status = exit.status;
+    if exit.terminated {
+        Err(format!(
+            "Editor '{editor}' was stopped by a signal, so the file was not updated."
+        ))
+    } else if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Editor '{editor}' exited unsuccessfully ({status}), so the file was not updated."
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plaintext_file_is_private_and_removed() {
+        let plaintext =
+            PlaintextFile::create(Path::new("dir/secrets.json.iron"), b"secret").unwrap();
+        let path = plaintext.path().to_path_buf();
+        assert_eq!(path.extension().unwrap(), "json");
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(plaintext);
+        assert!(!path.exists());
+    }
+}
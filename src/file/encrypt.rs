@@ -1,17 +1,31 @@
+use super::{
+    armor, bundle,
+    compress::{self, Codec},
+    decrypt,
+    envelope::Envelope,
+    is_encrypted_file,
+    naming::NamingArgs,
+    output::{self, OutputFile, Overwrite, OverwriteArgs},
+    stream, unmanaged,
+};
 use crate::{
+    filter::FilterArgs,
     group_maps::{convert_group_names_to_ids, get_group_maps},
+    jobs::JobsArgs,
     util::{self, act_on_all_files},
 };
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use ironoxide::prelude::*;
 use itertools::Itertools;
 use itertools::{Either, EitherOrBoth};
 use prettytable::Row;
+use serde::Serialize;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
-    fs::{self, OpenOptions},
-    io::{self, Read, Write},
-    path::PathBuf,
+    fs::{self, File},
+    io::{self, Cursor, Read, Write},
+    path::{MAIN_SEPARATOR, Path, PathBuf},
 };
 use yansi::Paint;
 
@@ -20,6 +34,9 @@ const EXAMPLES: &str = "EXAMPLES
     Encrypt the provided file and write the results to 'path/to/file.iron'.
         $ ironhide file encrypt path/to/file
 
+    Re-encrypt the provided file, replacing 'path/to/file.iron' if it already exists.
+        $ ironhide file encrypt path/to/file --force
+
     Encrypt the provided file and grant decryption access to two users and one group
         $ ironhide file encrypt -g myGroup -u john@example.com,mike@example.com path/to/file
 
@@ -30,22 +47,100 @@ const EXAMPLES: &str = "EXAMPLES
     the original unencrypted 'path/to/file.txt' file.
         $ ironhide file encrypt path/to/file.txt -d
 
+    Encrypt the provided file, then overwrite and delete the original once the encrypted file has been verified.
+        $ ironhide file encrypt path/to/file.txt -d --shred
+
     Encrypt all of the JSON files in the current directory and write them out to '.iron' files.
         $ ironhide file encrypt *.json
 
+    Encrypt all of the JSON files in the current directory into 'encrypted/', creating it if needed.
+        $ ironhide file encrypt *.json -o encrypted/ --mkdir
+
+    Encrypt every file under 'config/' and write the results into 'encrypted/', preserving the directory structure.
+        $ ironhide file encrypt -r config/ -o encrypted/
+
+    Encrypt every file under 'logs/', working on 8 files at a time.
+        $ ironhide file encrypt -r logs/ --jobs 8
+
+    Encrypt the provided file and write the results to 'path/to/file.enc'.
+        $ ironhide file encrypt path/to/file --output-template '{name}.enc'
+
+    Pack the 'config/' directory into a single encrypted bundle at 'config.iron'.
+        $ ironhide file encrypt --bundle config/
+
     Encrypt the provided file and write the encrypted content to stdout.
         $ ironhide file encrypt path/to/file -o -
 
-    Encrypt stdin and write the encrypted results to stdout in base64.
-        $ echo \"my secret\" | ironhide file encrypt -s -o - | base64 -e
+    Encrypt stdin and write ASCII-armored results to stdout, ready to paste into an email or ticket.
+        $ echo \"my secret\" | ironhide file encrypt -s -o - --armor
+
+    Encrypt a large database dump in segments so it's never fully loaded into memory.
+        $ pg_dump mydb | ironhide file encrypt --stream -s -o mydb.sql.iron
+
+    Encrypt a file with an explicit document ID and name so it can be tracked in other systems.
+        $ ironhide file encrypt report.pdf --id report-2024-q1 --name \"Q1 report\"
+
+    Encrypt a file along with its name, permissions and modification time, which 'file decrypt --preserve' restores.
+        $ ironhide file encrypt deploy.sh --preserve
+
+    Encrypt a new secret to the same users and groups as an existing one.
+        $ ironhide file encrypt db-password --same-access-as api-key.iron
+
+    Encrypt a customer export to whoever the data control policy says should see restricted PII.
+        $ ironhide file encrypt customers.csv --category PII --sensitivity RESTRICTED
+
+    Encrypt a report only for the 'security' group, so that you can't decrypt it afterwards.
+        $ ironhide file encrypt report.pdf -g security --no-self
+
+    Compress a large JSON export with zstd before encrypting it. 'file decrypt' decompresses it automatically.
+        $ ironhide file encrypt export.json --compress
+
+    Compress every file under 'exports/' with gzip, except for those that are already compressed like archives.
+        $ ironhide file encrypt -r exports/ --compress=gzip --auto
+
+    Encrypt every file under 'dist/' and record what was produced in 'manifest.json'.
+        $ ironhide file encrypt -r dist/ --manifest manifest.json
+
+    Encrypt a file to the 'compliance' group without the IronCore service keeping any record of it.
+        $ ironhide file encrypt audit.csv --unmanaged -g compliance
 ";
 
 #[derive(Parser)]
 #[clap(after_help = EXAMPLES)]
+#[clap(group(ArgGroup::new("policy").multiple(true).args(["category", "sensitivity", "data_subject"])))]
+#[clap(group(ArgGroup::new("recipients").multiple(true).args(["users", "groups", "same_access_as", "category", "sensitivity", "data_subject"])))]
 /// Encrypt a file or list of files to yourself and optionally to other users or groups. By default, the input file is
 /// unchanged and the output uses the same filename with a '.iron' extension added.
 pub struct Encrypt {
-    /// Delete the unencrypted source file(s) after successful encryption.
+    /// Write the encrypted output as ASCII-armored text that can be pasted into email, tickets or chat. Armored files
+    /// are detected automatically by the other file commands.
+    #[clap(short, long, num_args = 0)]
+    armor: bool,
+    /// With '--compress', leave files whose contents are already compressed, such as archives, images and video,
+    /// uncompressed.
+    #[clap(long, num_args = 0, requires = "compress")]
+    auto: bool,
+    /// Pack the provided directory, including its file permissions and relative paths, into a single encrypted
+    /// bundle. By default the bundle is written next to the directory with a '.iron' extension. Use
+    /// 'file decrypt --extract' to unpack it.
+    #[clap(long, num_args = 0, conflicts_with_all = ["delete", "recursive", "stdin"])]
+    bundle: bool,
+    /// Also encrypt to the users and groups that your segment's data control policy selects for this category of
+    /// data, e.g. 'PII'. Can be combined with '--sensitivity', '--data-subject', '-u' and '-g'.
+    #[clap(value_parser = parse_category, long)]
+    category: Option<Category>,
+    /// Compress the file(s) before encrypting them, which makes text such as JSON and logs much smaller. Uses zstd
+    /// unless '--compress=gzip' is given. The codec is stored inside the encrypted file, and 'file decrypt'
+    /// decompresses automatically. Files that compress more than 100 times beyond their first 64 MiB are refused, as
+    /// decompressing them is indistinguishable from a compression bomb.
+    #[clap(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "zstd")]
+    compress: Option<Codec>,
+    /// Also encrypt to the users and groups that your segment's data control policy selects for data about this kind
+    /// of subject, e.g. 'PATIENT'.
+    #[clap(value_parser = parse_data_subject, long)]
+    data_subject: Option<DataSubject>,
+    /// Delete the unencrypted source file(s) after successful encryption. A source is only deleted once its encrypted
+    /// output has been decrypted and matches it, and access was granted to every requested user and group.
     #[clap(short, long, num_args = 0)]
     delete: bool,
     /// Path of file(s) to encrypt.
@@ -55,35 +150,171 @@ pub struct Encrypt {
     /// Can refer to a group by ID or by name. Indicate IDs by prefixing with 'id^' e.g. 'id^groupID'.
     #[clap(value_parser = util::group_identifier_from_string, short, long, use_value_delimiter = true, value_delimiter = ',')]
     groups: Vec<Either<GroupName, GroupId>>,
+    /// ID to give the encrypted document, so it can be tracked across systems. Only allowed when a single file is
+    /// being encrypted. If not provided a random ID is generated.
+    #[clap(value_parser = parse_document_id, long, conflicts_with = "recursive")]
+    id: Option<DocumentId>,
     /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
     /// '~/.iron' directory.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
     keyfile: Option<PathBuf>,
-    /// Filename where encrypted file will be written. Only allowed if a single file is
-    /// being encrypted.
-    /// Use '-o -' to write encrypted file content to stdout, but fair warning, the output is binary and not ASCII.
+    /// Write a JSON manifest of the encrypted files to the given path. For every file that was encrypted it records the
+    /// input and output paths, the document ID and name, the size and SHA-256 of the plaintext, and the users and
+    /// groups it was encrypted to.
+    #[clap(value_parser = clap::value_parser!(PathBuf), long)]
+    manifest: Option<PathBuf>,
+    /// Create the output directory given with '-o' if it doesn't already exist when encrypting into a directory.
+    #[clap(long, num_args = 0, requires = "out")]
+    mkdir: bool,
+    /// Name to give the encrypted document, which is shown by 'file info'. Defaults to the name of the file being
+    /// encrypted. Can be changed later with 'file rename'.
+    #[clap(value_parser = parse_document_name, short, long)]
+    name: Option<DocumentName>,
+    /// Don't encrypt to yourself, e.g. when submitting a file that only the recipients should be able to read. You
+    /// won't be able to decrypt the encrypted file(s) unless you're in one of the groups they're encrypted to, so
+    /// you're asked to confirm first. Nothing is written if no other user or group was granted access. Can't be used
+    /// with '--delete', since the encrypted output can't be checked against the source before deleting it.
+    #[clap(long, num_args = 0, requires = "recipients", conflicts_with = "delete")]
+    no_self: bool,
+    /// Filename where encrypted file will be written, or a directory to write the encrypted file(s) into. Has to be a
+    /// directory when encrypting multiple files, and with '-r' the results are written to that directory with the same
+    /// structure as the input. Use '-o -' to write encrypted file content to stdout, but fair warning, the output is binary and not ASCII
+    /// unless '--armor' is used.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
     out: Option<PathBuf>,
+    /// Store the file's name, permissions, modification time and a SHA-256 of its contents inside the encrypted file.
+    /// Nothing is visible without decrypting. 'file decrypt' checks the contents against the stored SHA-256, and
+    /// 'file decrypt --preserve' restores the rest.
+    #[clap(long, num_args = 0, conflicts_with_all = ["bundle", "stdin"])]
+    preserve: bool,
+    /// Encrypt every file in the provided directories and their subdirectories. Files that already have the '--suffix',
+    /// '.iron' by default, are skipped. Files that are already encrypted are always skipped, even when given directly. When used with '-o', the results are written to that directory with the same
+    /// structure as the input.
+    #[clap(short, long, num_args = 0, conflicts_with = "stdin")]
+    recursive: bool,
+    /// Also encrypt to every user and group who can decrypt the given encrypted file, so the new file is shared the
+    /// same way. Can be combined with '-u' and '-g'.
+    #[clap(value_parser = clap::value_parser!(PathBuf), long)]
+    same_access_as: Option<PathBuf>,
+    /// Also encrypt to the users and groups that your segment's data control policy selects for data of this
+    /// sensitivity, e.g. 'RESTRICTED'.
+    #[clap(value_parser = parse_sensitivity, long)]
+    sensitivity: Option<Sensitivity>,
+    /// Overwrite the source file(s) with zeros before they're deleted. Only allowed with '--delete'. Copy-on-write
+    /// filesystems and SSDs may keep the original blocks, so this is a best effort.
+    #[clap(long, num_args = 0, requires = "delete")]
+    shred: bool,
     /// Read data to encrypt from stdin. If used, no source files should be provided as
     /// arguments and you must use the '-o' flag.
     #[clap(short, long, num_args = 0, conflicts_with = "files", requires = "out")]
     stdin: bool,
+    /// Encrypt in fixed-size segments so that memory use stays bounded regardless of the size of the input. Recommended
+    /// for very large files. Streamed files are detected automatically by 'file decrypt' and 'file info'.
+    #[clap(long, num_args = 0)]
+    stream: bool,
+    /// User to put in place of '%USER%' in the data control policy's rules. Defaults to the logged-in user.
+    #[clap(value_parser = parse_user_id, long, requires = "policy")]
+    substitute_user: Option<UserId>,
+    /// Encrypt without registering the document with the IronCore service. The encrypted keys are stored in the
+    /// output file alongside the data, so the service keeps no record of the file. Access is fixed at encryption time
+    /// and can't be changed later with 'file grant' or 'file revoke'.
+    #[clap(long, num_args = 0, conflicts_with = "stream")]
+    unmanaged: bool,
     /// Encrypt the file(s) to a comma-separated list of user emails. Files are
     /// automatically encrypted to the logged-in user.
     #[clap(value_parser = parse_user_id, short, long, use_value_delimiter = true, value_delimiter = ',', required = false)]
     users: Vec<UserId>,
+    /// Don't ask for confirmation when using '--no-self'. Required with '--stdin', as stdin can't be used for both.
+    #[clap(short, long, num_args = 0, requires = "no_self")]
+    yes: bool,
+    #[clap(flatten)]
+    overwrite: OverwriteArgs,
+    #[clap(flatten)]
+    naming: NamingArgs,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
 }
 
 fn parse_user_id(s: &str) -> Result<UserId, IronOxideErr> {
     UserId::try_from(s)
 }
 
+fn parse_category(s: &str) -> Result<Category, IronOxideErr> {
+    Category::try_from(s)
+}
+
+fn parse_sensitivity(s: &str) -> Result<Sensitivity, IronOxideErr> {
+    Sensitivity::try_from(s)
+}
+
+fn parse_data_subject(s: &str) -> Result<DataSubject, IronOxideErr> {
+    DataSubject::try_from(s)
+}
+
+fn parse_document_id(s: &str) -> Result<DocumentId, IronOxideErr> {
+    DocumentId::try_from(s)
+}
+
+pub(crate) fn parse_document_name(s: &str) -> Result<DocumentName, IronOxideErr> {
+    DocumentName::try_from(s)
+}
+
 impl util::GetKeyfile for Encrypt {
     fn get_keyfile(&self) -> Option<&PathBuf> {
         self.keyfile.as_ref()
     }
 }
 
+impl Encrypt {
+    /// Grant to whoever the data control policy selects for the given category, sensitivity and data subject, if any
+    /// of them were provided.
+    fn policy_grant(&self) -> Option<PolicyGrant> {
+        (self.category.is_some() || self.sensitivity.is_some() || self.data_subject.is_some()).then(
+            || {
+                PolicyGrant::new(
+                    self.category.clone(),
+                    self.sensitivity.clone(),
+                    self.data_subject.clone(),
+                    self.substitute_user.clone(),
+                )
+            },
+        )
+    }
+
+    /// The policy rules that were asked for, e.g. "category 'PII', sensitivity 'RESTRICTED'".
+    fn policy_rules(&self) -> String {
+        [
+            ("category", self.category.as_ref().map(|c| c.inner())),
+            ("sensitivity", self.sensitivity.as_ref().map(|s| s.inner())),
+            (
+                "data subject",
+                self.data_subject.as_ref().map(|d| d.inner()),
+            ),
+            ("user", self.substitute_user.as_ref().map(|u| u.id())),
+        ]
+        .into_iter()
+        .filter_map(|(rule, value)| value.map(|value| format!("{rule} '{value}'")))
+        .join(", ")
+    }
+}
+
+/// Warn that the caller won't be able to decrypt what they're encrypting, and ask whether to go ahead.
+fn confirm_no_self(encrypt: &Encrypt) -> Result<bool, String> {
+    util::println_paint(Paint::yellow(
+        "Warning! With '--no-self' you won't be able to decrypt the encrypted file(s) unless you're in one of the groups they're encrypted to.".to_string(),
+    ));
+    if encrypt.yes {
+        Ok(true)
+    } else if encrypt.stdin {
+        Err("Use '--yes' to confirm '--no-self' when encrypting stdin.".to_string())
+    } else {
+        promptly::prompt_default(format!("{}", Paint::magenta("Continue?")), false)
+            .map_err(|e| e.to_string())
+    }
+}
+
 fn display_group_name_and_id(meta: &GroupMetaResult) -> String {
     format!(
         "{} ({})",
@@ -94,48 +325,96 @@ fn display_group_name_and_id(meta: &GroupMetaResult) -> String {
     )
 }
 
-pub fn encrypt_files(
-    sdk: &BlockingIronOxide,
-    Encrypt {
-        delete,
-        files,
-        groups,
-        out,
-        stdin,
-        users,
-        ..
-    }: Encrypt,
-) -> Result<(), String> {
-    if stdin {
-        let mut file: Vec<u8> = vec![];
-        match io::stdin().read_to_end(&mut file) {
-            Ok(_) => {
-                match encrypt_file(&out, None, sdk, &groups, &users, file, delete) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        util::println_paint(Paint::red(format!("Error encrypting file: {}", e)))
-                    }
-                };
-            }
-            Err(e) => util::println_paint(Paint::red(format!("Error reading stdin: {}", e))),
+pub fn encrypt_files(sdk: &BlockingIronOxide, mut encrypt: Encrypt) -> Result<(), String> {
+    if encrypt.no_self && !confirm_no_self(&encrypt)? {
+        return Ok(());
+    }
+    if let Some(reference) = &encrypt.same_access_as {
+        util::add_access_from(sdk, reference, &mut encrypt.users, &mut encrypt.groups)?;
+    }
+    if encrypt.stdin {
+        match encrypt_file(sdk, &encrypt, None, &encrypt.out, &mut io::stdin().lock()) {
+            Ok(encrypt_result) => write_manifest(&encrypt, encrypt_result.as_slice())?,
+            Err(e) => util::println_paint(Paint::red(format!("Error encrypting file: {}", e))),
+        };
+    } else if encrypt.bundle {
+        if let Some(encrypt_result) = encrypt_bundle(sdk, &encrypt)? {
+            write_manifest(&encrypt, std::slice::from_ref(&encrypt_result))?;
+            print_resolved_grants(encrypt_result);
         }
-    } else if out.is_some() && files.len() > 1 {
+    } else if encrypt.files.len() > 1 && encrypt.out.as_deref().is_some_and(output::is_stdout) {
         util::println_paint(Paint::red(
-            "Cannot use '-o' flag with multiple files.".to_string(),
+            "Cannot use '-o -' with multiple files. Provide an output directory instead."
+                .to_string(),
+        ));
+    } else if encrypt.id.is_some() && encrypt.files.len() > 1 {
+        util::println_paint(Paint::red(
+            "Cannot use '--id' with multiple files as document IDs must be unique.".to_string(),
+        ));
+    } else if encrypt.recursive && encrypt.out == Some(PathBuf::from("-")) {
+        util::println_paint(Paint::red(
+            "Cannot use '-o -' with '-r'. Provide an output directory instead.".to_string(),
         ));
     } else {
+        let files =
+            util::collect_files(&encrypt.files, encrypt.recursive, &encrypt.filter, |path| {
+                !encrypt.naming.has_suffix(path)
+            })?;
+        // encrypting an encrypted file again is almost always a mistake, e.g. one that was renamed
+        let (encrypted, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|path| is_encrypted_file(sdk, path));
+        for path in encrypted {
+            util::println_paint(Paint::yellow(format!(
+                "Skipped '{}' as it's already encrypted.",
+                path.display()
+            )));
+        }
+        // a single file can be encrypted into a directory too, which a trailing separator marks as one
+        let into_dir = !encrypt.recursive
+            && encrypt.out.as_deref().is_some_and(|out| {
+                encrypt.files.len() > 1
+                    || out.is_dir()
+                    || out.to_string_lossy().ends_with(MAIN_SEPARATOR)
+            });
+        if let Some(out_dir) = &encrypt.out
+            && into_dir
+        {
+            util::prepare_output_dir(out_dir, encrypt.mkdir)?;
+        }
+        let out_paths = files
+            .iter()
+            .map(|infile| {
+                let out = match &encrypt.out {
+                    Some(out_dir) if encrypt.recursive => Some(
+                        util::mirror_output_path(out_dir, infile, &encrypt.files)?
+                            .with_file_name(encrypt.naming.encrypted_name(infile)?),
+                    ),
+                    Some(out_dir) if into_dir => {
+                        Some(out_dir.join(encrypt.naming.encrypted_name(infile)?))
+                    }
+                    _ => encrypt.out.clone(),
+                };
+                encrypt_output_path(out, Some(infile), &encrypt.naming)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        util::check_output_collisions(&files, &out_paths)?;
+        let out_paths = files.iter().zip(out_paths).collect::<HashMap<_, _>>();
         let result = act_on_all_files(
             &files,
-            |infile| -> Result<EncryptResultWithResolved, String> {
-                let file = fs::read(infile).map_err(|e| {
+            encrypt.jobs.count(),
+            |infile| -> Result<Option<EncryptResultWithResolved>, String> {
+                let mut file = File::open(infile).map_err(|e| {
                     format!(
                         "Provided path '{}' doesn't exist or is not readable: {e}",
                         infile.display()
                     )
                 })?;
-                let encrypt_result =
-                    encrypt_file(&out, Some(infile), sdk, &groups, &users, file, delete)?;
-                if files.len() == 1 {
+                let out = Some(out_paths[infile].clone());
+                let encrypt_result = encrypt_file(sdk, &encrypt, Some(infile), &out, &mut file)?;
+                if let Some(encrypt_result) = &encrypt_result
+                    && (files.len() == 1 || encrypt.recursive)
+                {
                     util::println_paint(Paint::green(format!(
                         "Encrypted file successfully written to {}.",
                         encrypt_result.output_log.clone()
@@ -144,25 +423,157 @@ pub fn encrypt_files(
                 Ok(encrypt_result)
             },
             "encrypted",
-        )
-        .map_err(|(e, maybe_success)| {
-            maybe_success.map(|success| print_resolved_grants(success));
-            e
-        })?;
-        result.map(|encrypt_result| print_resolved_grants(encrypt_result));
+        );
+        let (results, failure) = match result {
+            Ok(results) => (results, None),
+            Err((e, results)) => (results, Some(e)),
+        };
+        // skipped files weren't written, so they're left out of the manifest
+        let results = results.into_iter().flatten().collect_vec();
+        write_manifest(&encrypt, &results)?;
+        if let Some(encrypt_result) = results.into_iter().next() {
+            print_resolved_grants(encrypt_result);
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
     }
 
     Ok(())
 }
 
+/// Pack the single directory given in `encrypt.files` into a bundle and encrypt it. Returns `None` if the output
+/// already exists and is being skipped.
+fn encrypt_bundle(
+    sdk: &BlockingIronOxide,
+    encrypt: &Encrypt,
+) -> Result<Option<EncryptResultWithResolved>, String> {
+    let dir = match encrypt.files.as_slice() {
+        [dir] if dir.is_dir() => dir,
+        _ => return Err("'--bundle' requires a single directory to encrypt.".to_string()),
+    };
+    let (archive, skipped) = bundle::pack(dir)?;
+    for path in skipped {
+        util::println_paint(Paint::yellow(format!(
+            "Skipped '{}' as only regular files and directories can be bundled.",
+            path.display()
+        )));
+    }
+    let out = match &encrypt.out {
+        Some(out) => out.clone(),
+        None => default_bundle_output(dir, &encrypt.naming)?,
+    };
+    let encrypt_result = encrypt_file(sdk, encrypt, Some(dir), &Some(out), &mut &archive[..])?;
+    if let Some(encrypt_result) = &encrypt_result {
+        util::println_paint(Paint::green(format!(
+            "Encrypted bundle successfully written to {}.",
+            encrypt_result.output_log
+        )));
+    }
+    Ok(encrypt_result)
+}
+
+/// Bundles are written next to their directory, e.g. 'path/to/config/' is written to 'path/to/config.iron'.
+fn default_bundle_output(dir: &Path, naming: &NamingArgs) -> Result<PathBuf, String> {
+    // normalizing through components drops any trailing slash, and falls back to the real path for '.' or '..'
+    let normalized = match dir.components().collect::<PathBuf>() {
+        normalized if normalized.file_name().is_some() => normalized,
+        _ => fs::canonicalize(dir)
+            .map_err(|e| format!("Invalid directory '{}': {e}", dir.display()))?,
+    };
+    Ok(normalized.with_file_name(naming.encrypted_name(&normalized)?))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry<'a> {
+    input: Option<&'a Path>,
+    output: &'a Path,
+    document_id: &'a str,
+    document_name: Option<&'a str>,
+    plaintext_size: u64,
+    plaintext_sha256: String,
+    users: Vec<&'a str>,
+    groups: Vec<ManifestGroup<'a>>,
+}
+
+#[derive(Serialize)]
+struct ManifestGroup<'a> {
+    id: &'a str,
+    name: Option<&'a str>,
+}
+
+/// Write the '--manifest', if one was asked for, describing each of the encrypted files.
+fn write_manifest(
+    encrypt: &Encrypt,
+    encrypt_results: &[EncryptResultWithResolved],
+) -> Result<(), String> {
+    let Some(manifest_path) = &encrypt.manifest else {
+        return Ok(());
+    };
+    let entries = encrypt_results
+        .iter()
+        .map(|encrypt_result| ManifestEntry {
+            input: encrypt_result.input.as_deref(),
+            output: &encrypt_result.output,
+            document_id: encrypt_result.id.id(),
+            document_name: encrypt_result
+                .name
+                .as_ref()
+                .map(|name| name.name().as_str()),
+            plaintext_size: encrypt_result.plaintext_size,
+            plaintext_sha256: util::to_hex(&encrypt_result.plaintext_sha256),
+            users: encrypt_result
+                .resolved_users
+                .iter()
+                .map(|user| user.id())
+                .collect(),
+            groups: encrypt_result
+                .resolved_groups
+                .iter()
+                .map(|group| ManifestGroup {
+                    id: group.id().id(),
+                    name: group.name().map(|name| name.name().as_str()),
+                })
+                .collect(),
+        })
+        .collect_vec();
+    let json = serde_json::to_vec_pretty(&entries).expect("manifest is always serializable");
+    let mut output = OutputFile::create(manifest_path, Overwrite::Force)?;
+    output
+        .write_all(&json)
+        .map_err(|e| format!("Couldn't write manifest: {e}"))?;
+    let output_log = output.log_path();
+    output.finish()?;
+    util::println_paint(Paint::green(format!("Manifest written to {output_log}.")));
+    Ok(())
+}
+
 fn print_resolved_grants(encrypt_result: EncryptResultWithResolved) {
     util::println_paint(Paint::green(
         "\nSuccessfully encrypted to the following users and groups:".to_string(),
     ));
-    let zipped = encrypt_result
-        .resolved_users
-        .into_iter()
-        .zip_longest(encrypt_result.resolved_groups);
+    print_grants_table(
+        encrypt_result.resolved_users,
+        encrypt_result.resolved_groups,
+    );
+    if let Some(policy) = encrypt_result.policy {
+        util::println_paint(Paint::green(format!(
+            "\nThe policy for {} resolved to:",
+            policy.rules
+        )));
+        if policy.users.is_empty() && policy.groups.is_empty() {
+            util::println_paint(Paint::yellow(
+                "No users or groups other than those requested explicitly.".to_string(),
+            ));
+        } else {
+            print_grants_table(policy.users, policy.groups);
+        }
+    }
+}
+
+fn print_grants_table(users: Vec<UserId>, groups: Vec<GroupMetaResult>) {
+    let zipped = users.into_iter().zip_longest(groups);
     let mut table = table!([Fbb=>"Users", "Groups"]);
     zipped.for_each(|either_or_both| {
         let row = match either_or_both {
@@ -177,19 +588,62 @@ fn print_resolved_grants(encrypt_result: EncryptResultWithResolved) {
     table.printstd();
 }
 
+/// Encrypt `input` and write it out, deleting `infile` afterwards if requested. Returns `None` without encrypting if
+/// the output already exists and is being skipped.
 fn encrypt_file(
-    out: &Option<PathBuf>,
-    infile: Option<&PathBuf>,
     sdk: &BlockingIronOxide,
-    groups: &[Either<GroupName, GroupId>],
-    users: &[UserId],
-    file: Vec<u8>,
-    delete: bool,
-) -> Result<EncryptResultWithResolved, String> {
+    encrypt: &Encrypt,
+    infile: Option<&PathBuf>,
+    out: &Option<PathBuf>,
+    input: &mut dyn Read,
+) -> Result<Option<EncryptResultWithResolved>, String> {
+    let out_path = encrypt_output_path(out.clone(), infile, &encrypt.naming)?;
+    if output::should_skip(&out_path, encrypt.overwrite.policy()) {
+        return Ok(None);
+    }
     let (groups_by_name, groups_by_id) = get_group_maps(sdk);
-    let group_ids = convert_group_names_to_ids(groups, &groups_by_name);
-    let users_or_groups = util::collect_users_and_groups(users, &group_ids);
-    let encrypt_result = encrypt_bytes_to_file(sdk, &file, &users_or_groups, out, infile)?;
+    let group_ids = convert_group_names_to_ids(&encrypt.groups, &groups_by_name);
+    let users_or_groups = util::collect_users_and_groups(&encrypt.users, &group_ids);
+    // '--preserve' can't be used with stdin or bundles, so `infile` is always a regular file here
+    let header = match infile {
+        Some(infile) if encrypt.preserve => Envelope::for_file(infile)
+            .map_err(|e| format!("Couldn't read metadata for '{}': {e}", infile.display()))?
+            .to_header(),
+        _ => Vec::new(),
+    };
+    // the plaintext is measured without the envelope or compression, so it matches the input
+    let mut plaintext = util::Sha256Reader::new(input);
+    let encrypt_result = {
+        let mut input = compressed_input(encrypt, Cursor::new(header), &mut plaintext)?;
+        encrypt_to_file(
+            sdk,
+            encrypt,
+            &mut input,
+            &users_or_groups,
+            &out_path,
+            infile,
+        )?
+    };
+    let (plaintext_size, plaintext_sha256) = plaintext.finish();
+    let missing = missing_grants(encrypt, &groups_by_name, &encrypt_result.grants);
+    if let Some(reference) = &encrypt.same_access_as
+        && !missing.is_empty()
+    {
+        util::println_paint(Paint::yellow(format!(
+            "{} doesn't have the same access as '{}', as access wasn't granted to {}.",
+            encrypt_result.output_log,
+            reference.display(),
+            missing.join(", ")
+        )));
+    }
+    if encrypt.delete {
+        match infile {
+            Some(infile) => delete_source(sdk, encrypt, infile, &out_path, &missing),
+            None => util::println_paint(Paint::yellow(
+                "Unable to delete source as it was a stream.".to_string(),
+            )),
+        }
+    }
     let (resolved_users, maybe_resolved_groups): (Vec<UserId>, Vec<Option<GroupMetaResult>>) =
         encrypt_result
             .grants
@@ -199,141 +653,285 @@ fn encrypt_file(
                 UserOrGroup::Group { id } => Either::Right(groups_by_id.get(&id).cloned()),
             });
     let resolved_groups = maybe_resolved_groups.into_iter().flatten().collect_vec();
-    if delete {
-        match infile {
-            Some(infile) => {
-                if fs::remove_file(infile).is_err() {
-                    util::println_paint(Paint::yellow(format!(
-                        "Unable to delete source file '{}' as it is not writable.",
-                        &infile.display()
-                    )))
-                }
-            }
-            None => util::println_paint(Paint::yellow(
-                "Unable to delete source as it was a stream.".to_string(),
-            )),
+    // anyone granted access who wasn't asked for explicitly, and isn't the caller, was selected by the policy
+    let policy = encrypt.policy_grant().map(|_| {
+        let explicit = |u_or_g: &UserOrGroup| match u_or_g {
+            UserOrGroup::User { id } => !encrypt.no_self && id == sdk.device().account_id(),
+            UserOrGroup::Group { .. } => false,
+        } || users_or_groups.contains(u_or_g);
+        PolicyResolved {
+            rules: encrypt.policy_rules(),
+            users: resolved_users
+                .iter()
+                .filter(|id| !explicit(&UserOrGroup::User { id: (*id).clone() }))
+                .cloned()
+                .collect(),
+            groups: resolved_groups
+                .iter()
+                .filter(|group| {
+                    !explicit(&UserOrGroup::Group {
+                        id: group.id().clone(),
+                    })
+                })
+                .cloned()
+                .collect(),
         }
-    }
-    Ok(EncryptResultWithResolved {
+    });
+    Ok(Some(EncryptResultWithResolved {
+        input: infile.cloned(),
+        output: out_path,
+        id: encrypt_result.id,
+        name: document_name(encrypt, infile),
+        plaintext_size,
+        plaintext_sha256,
         resolved_users,
         resolved_groups,
+        policy,
         output_log: encrypt_result.output_log,
-    })
+    }))
+}
+
+/// The `header` followed by the `contents`, compressed if '--compress' was used. With '--auto', contents that are
+/// already compressed are left as they are.
+fn compressed_input<'a>(
+    encrypt: &Encrypt,
+    header: impl Read + 'a,
+    contents: impl Read + 'a,
+) -> Result<Box<dyn Read + 'a>, String> {
+    let compress_err = |e: io::Error| format!("Couldn't compress data to encrypt: {e}");
+    let Some(codec) = encrypt.compress else {
+        return Ok(Box::new(header.chain(contents)));
+    };
+    let (already_compressed, contents) = compress::sniff(contents).map_err(compress_err)?;
+    if encrypt.auto && already_compressed {
+        Ok(Box::new(header.chain(contents)))
+    } else {
+        compress::compress(codec, header.chain(contents)).map_err(compress_err)
+    }
+}
+
+/// Users and groups that were asked for on the command line but weren't granted access, e.g. because a group name
+/// didn't match any group or the user doesn't exist.
+fn missing_grants(
+    encrypt: &Encrypt,
+    groups_by_name: &HashMap<GroupName, Vec<GroupMetaResult>>,
+    grants: &[UserOrGroup],
+) -> Vec<String> {
+    let granted_group = |id: &GroupId| grants.contains(&UserOrGroup::Group { id: id.clone() });
+    let missing_users = encrypt
+        .users
+        .iter()
+        .filter(|user| {
+            !grants.contains(&UserOrGroup::User {
+                id: (*user).clone(),
+            })
+        })
+        .map(|user| user.id().to_string());
+    let missing_groups = encrypt
+        .groups
+        .iter()
+        .filter(|group| match group {
+            Either::Left(name) => !groups_by_name
+                .get(name)
+                .is_some_and(|groups| groups.iter().any(|group| granted_group(group.id()))),
+            Either::Right(id) => !granted_group(id),
+        })
+        .map(|group| match group {
+            Either::Left(name) => name.name().to_string(),
+            Either::Right(id) => format!("id^{}", id.id()),
+        });
+    missing_users.chain(missing_groups).collect()
+}
+
+/// Delete `infile` once it's safe to do so. The encrypted output at `out_path` is decrypted and compared to `infile`
+/// first, and nothing is deleted if any of the requested grants are `missing`, as the source may be the only copy those
+/// users and groups could read.
+fn delete_source(
+    sdk: &BlockingIronOxide,
+    encrypt: &Encrypt,
+    infile: &Path,
+    out_path: &Path,
+    missing: &[String],
+) {
+    let refuse = |reason: String| {
+        util::println_paint(Paint::yellow(format!(
+            "Not deleting source file '{}' as {reason}.",
+            infile.display()
+        )))
+    };
+    if !missing.is_empty() {
+        return refuse(format!("access wasn't granted to {}", missing.join(", ")));
+    }
+    if output::is_stdout(out_path) {
+        return refuse("the encrypted output went to stdout and can't be verified".to_string());
+    }
+    let mut decrypted_hash = util::Sha256Writer::new();
+    if let Err(e) = decrypt::decrypt_to(sdk, out_path, &mut decrypted_hash) {
+        return refuse(format!("its encrypted output couldn't be verified: {e}"));
+    }
+    match util::sha256_file(infile) {
+        Ok(source_hash) if source_hash == decrypted_hash.finish() => (),
+        Ok(_) => {
+            return refuse("its encrypted output doesn't decrypt to the same contents".to_string());
+        }
+        Err(e) => {
+            return refuse(format!(
+                "it couldn't be read to verify the encrypted output: {e}"
+            ));
+        }
+    }
+    if encrypt.shred && util::overwrite_with_zeros(infile).is_err() {
+        return refuse("it couldn't be overwritten before deleting".to_string());
+    }
+    if fs::remove_file(infile).is_err() {
+        util::println_paint(Paint::yellow(format!(
+            "Unable to delete source file '{}' as it is not writable.",
+            &infile.display()
+        )))
+    }
 }
 
-/// Validate that the output path provided by the user can be used for encryption. If no path is provided,
-/// will append ".iron" to the input filename. Returns an Err if the input file ends with "..".
-fn validate_encrypt_output_path(
+/// Work out where the encrypted output should be written, where '-' is stdout. If no path is provided, the output is
+/// written next to the input and named by `naming`, and if a directory is provided the output goes inside it.
+fn encrypt_output_path(
     maybe_output: Option<PathBuf>,
     infile: Option<&PathBuf>,
-) -> Result<(Box<dyn Write>, String), String> {
-    let output: (Box<dyn Write>, String) = match maybe_output {
-        // User specified an output path.
-        Some(desired) => {
-            // User specified a directory for output
-            if desired.is_dir() && infile.is_some() {
-                let mut filename = infile
-                    // unwrap here is safe because of the .is_some check above
-                    .unwrap()
-                    .file_name()
-                    .ok_or_else(|| "Invalid input file".to_string())?
-                    .to_os_string();
-                filename.push(".iron");
-                let mut desired_dir = desired;
-                desired_dir.push(filename);
-                // if desired_dir.exists() {
-                //     return Err("BLOW UP".to_string())
-                // }
-                (
-                    Box::new(
-                        OpenOptions::new()
-                            .create_new(true)
-                            .append(true)
-                            .open(desired_dir.clone())
-                            .map_err(|e| {
-                                format!(
-                                    "Couldn't create a file in the desired output directory '{}': {e}",
-                                    desired_dir.display()
-                                )
-                            })?
-                    ),
-                    desired_dir.to_string_lossy().to_string(),
-                )
-            } else if desired == PathBuf::from("-") {
-                (Box::new(io::stdout()), "stdout".to_string())
-            } else {
-                (
-                    Box::new(
-                        OpenOptions::new()
-                            .create_new(true)
-                            .append(true)
-                            .open(desired.clone())
-                            .map_err(|e| {
-                                format!(
-                                    "Couldn't create a file at the desired output path '{}': {e}",
-                                    desired.display()
-                                )
-                            })?,
-                    ),
-                    desired.to_string_lossy().to_string(),
-                )
-            }
+    naming: &NamingArgs,
+) -> Result<PathBuf, String> {
+    match maybe_output {
+        // User specified a directory for output
+        Some(desired) if desired.is_dir() && infile.is_some() => {
+            // unwrap here is safe because of the .is_some check above
+            Ok(desired.join(naming.encrypted_name(infile.unwrap())?))
         }
-        // User didn't specify an output path. Add ".iron" to the input path.
+        // User specified an output path or stdout.
+        Some(desired) => Ok(desired),
+        // User didn't specify an output path. Name it after the input.
         None => match infile {
-            Some(infile) => {
-                let input_iron = infile.display().to_string() + ".iron";
-                (
-                    Box::new(
-                        OpenOptions::new()
-                            .create_new(true)
-                            .append(true)
-                            .open(PathBuf::from(input_iron.clone()))
-                            .map_err(|e| {
-                                format!("Couldn't create a file at the default output path '{input_iron}': {e}")
-                            })?,
-                    ),
-                    input_iron,
-                )
-            }
+            Some(infile) => Ok(infile.with_file_name(naming.encrypted_name(infile)?)),
             None => unreachable!(), // If stdin is used, output path is required, this is unreachable.
         },
-    };
-    Ok(output)
+    }
 }
 
 struct EncryptResult {
+    id: DocumentId,
     grants: Vec<UserOrGroup>,
     output_log: String,
 }
 
-#[derive(Clone)]
 struct EncryptResultWithResolved {
+    input: Option<PathBuf>,
+    output: PathBuf,
+    id: DocumentId,
+    name: Option<DocumentName>,
+    plaintext_size: u64,
+    plaintext_sha256: Vec<u8>,
     resolved_users: Vec<UserId>,
     resolved_groups: Vec<GroupMetaResult>,
+    /// Who the data control policy granted access to, if one was used.
+    policy: Option<PolicyResolved>,
     output_log: String,
 }
 
-/// Encrypt the provided file to the `users_or_groups`. The file will also be granted to the calling user.
-/// The bytes of the encrypted file will be written to `output_path`.
-fn encrypt_bytes_to_file(
+struct PolicyResolved {
+    rules: String,
+    users: Vec<UserId>,
+    groups: Vec<GroupMetaResult>,
+}
+
+/// Encrypt the provided input to the `users_or_groups`, and to whoever the data control policy selects if one was
+/// asked for. The file will also be granted to the calling user, unless '--no-self' was used.
+/// The bytes of the encrypted file will be written to `outfile`. When streaming, the input is encrypted in segments
+/// as it's read; otherwise it's read into memory and encrypted as a single document.
+fn encrypt_to_file(
     sdk: &BlockingIronOxide,
-    file: &[u8],
+    encrypt: &Encrypt,
+    input: &mut dyn Read,
     users_or_groups: &[UserOrGroup],
-    outfile: &Option<PathBuf>,
+    out_path: &Path,
     infile: Option<&PathBuf>,
 ) -> Result<EncryptResult, String> {
-    let grants = ExplicitGrant::new(true, users_or_groups);
-    let opts = DocumentEncryptOpts::new(None, None, EitherOrBoth::Left(grants));
-    let encrypt_result = sdk.document_encrypt(file.to_vec(), &opts)?;
-    let (mut output_writer, output_log) = validate_encrypt_output_path(outfile.clone(), infile)?;
-
-    output_writer
-        .write_all(encrypt_result.encrypted_data())
-        .map_err(|e| format!("Couldn't write encrypted file: {e}"))?;
-
-    Ok(EncryptResult {
-        grants: encrypt_result.grants().to_vec(),
-        output_log,
+    let explicit = ExplicitGrant::new(!encrypt.no_self, users_or_groups);
+    let grants = match encrypt.policy_grant() {
+        Some(policy) if encrypt.no_self && users_or_groups.is_empty() => {
+            EitherOrBoth::Right(policy)
+        }
+        Some(policy) => EitherOrBoth::Both(explicit, policy),
+        None => EitherOrBoth::Left(explicit),
+    };
+    let opts = DocumentEncryptOpts::new(encrypt.id.clone(), document_name(encrypt, infile), grants);
+    if encrypt.stream {
+        let mut output = OutputFile::create(out_path, encrypt.overwrite.policy())?;
+        let encrypt_result = armor::write_output(&mut output, encrypt.armor, |writer| {
+            stream::encrypt_stream(sdk, &opts, input, writer)
+        })?;
+        check_recipients(encrypt, encrypt_result.grants())?;
+        let output_log = output.log_path();
+        output.finish()?;
+        Ok(EncryptResult {
+            id: encrypt_result.id().clone(),
+            grants: encrypt_result.grants().to_vec(),
+            output_log,
+        })
+    } else {
+        let mut file = Vec::new();
+        input
+            .read_to_end(&mut file)
+            .map_err(|e| format!("Couldn't read data to encrypt: {e}"))?;
+        let (id, encrypted_data, grants) = if encrypt.unmanaged {
+            let encrypt_result = sdk.document_encrypt_unmanaged(file, &opts)?;
+            let container = unmanaged::encode(
+                encrypt_result.encrypted_deks(),
+                encrypt_result.encrypted_data(),
+            );
+            (
+                encrypt_result.id().clone(),
+                container,
+                encrypt_result.grants().to_vec(),
+            )
+        } else {
+            let encrypt_result = sdk.document_encrypt(file, &opts)?;
+            (
+                encrypt_result.id().clone(),
+                encrypt_result.encrypted_data().to_vec(),
+                encrypt_result.grants().to_vec(),
+            )
+        };
+        check_recipients(encrypt, &grants)?;
+        let mut output = OutputFile::create(out_path, encrypt.overwrite.policy())?;
+        armor::write_output(&mut output, encrypt.armor, |writer| {
+            writer
+                .write_all(&encrypted_data)
+                .map_err(|e| format!("Couldn't write encrypted file: {e}"))
+        })?;
+        let output_log = output.log_path();
+        output.finish()?;
+
+        Ok(EncryptResult {
+            id,
+            grants,
+            output_log,
+        })
+    }
+}
+
+/// Refuse to write out a document that nobody can decrypt, which can happen with '--no-self' when none of the other
+/// recipients could be granted access.
+fn check_recipients(encrypt: &Encrypt, grants: &[UserOrGroup]) -> Result<(), String> {
+    if encrypt.no_self && grants.is_empty() {
+        Err("Nothing was written as access couldn't be granted to any user or group, so nobody could decrypt it.".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Name for the document being encrypted. Without '--name' this is the input's file name, unless it isn't a valid
+/// document name, e.g. because it's too long, in which case the document is left unnamed.
+fn document_name(encrypt: &Encrypt, infile: Option<&PathBuf>) -> Option<DocumentName> {
+    encrypt.name.clone().or_else(|| {
+        infile
+            .and_then(|infile| infile.file_name())
+            .and_then(|file_name| DocumentName::try_from(file_name.to_string_lossy().as_ref()).ok())
     })
 }
@@ -0,0 +1,313 @@
+//! Optional metadata envelope stored at the start of the encrypted plaintext by 'file encrypt --preserve'.
+//!
+//! The envelope starts with `MAGIC`, followed by a length-prefixed JSON header holding the original file name,
+//! permission bits, modification time and SHA-256 of the file's contents. The file's contents follow the header. Since
+//! the envelope is encrypted along with the contents, none of the metadata is visible without decrypting.
+//!
+//! Decryption always strips the envelope and checks the digest. Restoring the metadata is up to the caller.
+
+use super::naming;
+use crate::util::{self, Sha256Writer};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: &[u8] = b"IHENVLP1";
+const LEN_SIZE: usize = 4;
+/// Upper bound on the size of the JSON header. Anything larger is treated as corruption rather than allocated.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Metadata about the original file.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Envelope {
+    pub name: Option<String>,
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch.
+    pub modified: Option<u64>,
+    /// Hex encoded SHA-256 of the file's contents.
+    pub sha256: String,
+}
+
+impl Envelope {
+    /// Collect the metadata for the file at `path`. The file is read to compute its digest.
+    pub fn for_file(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Ok(Envelope {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            mode,
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            sha256: util::to_hex(&util::sha256_file(path)?),
+        })
+    }
+
+    /// The envelope for new `contents` replacing the ones this envelope describes. The name and permissions are kept,
+    /// and the modification time is now.
+    pub fn for_new_contents(&self, contents: &[u8]) -> Self {
+        let mut hasher = Sha256Writer::new();
+        // writing to a hasher can't fail
+        hasher.write_all(contents).expect("hashing never fails");
+        Envelope {
+            name: self.name.clone(),
+            mode: self.mode,
+            modified: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since_epoch| since_epoch.as_secs()),
+            sha256: util::to_hex(&hasher.finish()),
+        }
+    }
+
+    /// The envelope header to write before the file's contents.
+    pub fn to_header(&self) -> Vec<u8> {
+        // serializing a struct of strings and numbers can't fail
+        let json = serde_json::to_vec(self).expect("envelope is always serializable");
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        header.extend_from_slice(&json);
+        header
+    }
+
+    /// The original name, if it's a plain file name that can't point outside of the directory it's restored into.
+    pub fn safe_name(&self) -> Option<&str> {
+        self.name.as_deref().and_then(naming::safe_file_name)
+    }
+
+    /// Restore the modification time and permission bits to the file at `path`. The permissions are applied last, so a
+    /// read-only mode can't get in the way of setting the modification time.
+    pub fn restore(&self, path: &Path) -> io::Result<()> {
+        if let Some(modified) = self.modified {
+            // on unix owning the file is enough to set its times, so it doesn't need to be writable
+            let file = if cfg!(unix) {
+                fs::File::open(path)?
+            } else {
+                fs::File::options().write(true).open(path)?
+            };
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(modified))?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        Ok(())
+    }
+
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        self.modified
+            .map(|modified| UNIX_EPOCH + Duration::from_secs(modified))
+    }
+}
+
+/// Writer that strips the envelope, if there is one, from the start of decrypted plaintext and passes the rest through
+/// to `inner`. Plaintext without an envelope is passed through unchanged.
+pub struct Unwrapper<W: Write> {
+    inner: W,
+    /// Bytes held back until it's known whether they're an envelope header.
+    pending: Option<Vec<u8>>,
+    envelope: Option<Envelope>,
+    hasher: Sha256Writer,
+}
+
+impl<W: Write> Unwrapper<W> {
+    pub fn new(inner: W) -> Self {
+        Unwrapper {
+            inner,
+            pending: Some(Vec::new()),
+            envelope: None,
+            hasher: Sha256Writer::new(),
+        }
+    }
+
+    /// Finish unwrapping, checking the contents against the envelope's digest. Returns the inner writer along with
+    /// the envelope, if there was one.
+    pub fn finish(mut self) -> Result<(W, Option<Envelope>), String> {
+        if let Some(pending) = self.pending.take() {
+            if pending.starts_with(MAGIC) {
+                return Err("Decrypted file has a truncated metadata envelope.".to_string());
+            }
+            self.inner
+                .write_all(&pending)
+                .map_err(|e| format!("Failed to write decrypted document: {e}"))?;
+        }
+        if let Some(envelope) = &self.envelope
+            && util::to_hex(&self.hasher.finish()) != envelope.sha256
+        {
+            return Err(
+                "Decrypted contents don't match the checksum stored when they were encrypted."
+                    .to_string(),
+            );
+        }
+        Ok((self.inner, self.envelope))
+    }
+
+    /// Try to parse the pending bytes, returning any bytes after the header once it's known whether there is one.
+    fn parse_pending(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let pending = self.pending.as_ref().expect("only called while pending");
+        let prefix_len = pending.len().min(MAGIC.len());
+        if pending[..prefix_len] != MAGIC[..prefix_len] {
+            return Ok(self.pending.take());
+        }
+        if pending.len() < MAGIC.len() + LEN_SIZE {
+            return Ok(None);
+        }
+        let len_bytes = &pending[MAGIC.len()..MAGIC.len() + LEN_SIZE];
+        let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        if len > MAX_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "metadata envelope is larger than allowed",
+            ));
+        }
+        let header_end = MAGIC.len() + LEN_SIZE + len;
+        if pending.len() < header_end {
+            return Ok(None);
+        }
+        self.envelope = Some(
+            serde_json::from_slice(&pending[MAGIC.len() + LEN_SIZE..header_end])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+        let mut pending = self.pending.take().expect("only called while pending");
+        Ok(Some(pending.split_off(header_end)))
+    }
+
+    fn write_body(&mut self, body: &[u8]) -> io::Result<()> {
+        if self.envelope.is_some() {
+            self.hasher.write_all(body)?;
+        }
+        self.inner.write_all(body)
+    }
+}
+
+impl<W: Write> Write for Unwrapper<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.pending {
+            Some(pending) => {
+                pending.extend_from_slice(buf);
+                if let Some(body) = self.parse_pending()? {
+                    self.write_body(&body)?;
+                }
+            }
+            None => self.write_body(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unwrap_in_chunks(
+        bytes: &[u8],
+        chunk_size: usize,
+    ) -> Result<(Vec<u8>, Option<Envelope>), String> {
+        let mut unwrapper = Unwrapper::new(Vec::new());
+        for chunk in bytes.chunks(chunk_size) {
+            unwrapper.write_all(chunk).map_err(|e| e.to_string())?;
+        }
+        unwrapper.finish()
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let contents = b"file contents";
+        let mut hasher = Sha256Writer::new();
+        hasher.write_all(contents).unwrap();
+        let envelope = Envelope {
+            name: Some("secrets.json".to_string()),
+            mode: Some(0o640),
+            modified: Some(1_700_000_000),
+            sha256: util::to_hex(&hasher.finish()),
+        };
+        let mut wrapped = envelope.to_header();
+        wrapped.extend_from_slice(contents);
+        for chunk_size in [1, 3, wrapped.len()] {
+            let (unwrapped, found) = unwrap_in_chunks(&wrapped, chunk_size).unwrap();
+            assert_eq!(unwrapped, contents);
+            assert_eq!(found.as_ref(), Some(&envelope));
+        }
+
+        // new contents get a new digest but keep the name and permissions
+        let updated = envelope.for_new_contents(b"new contents");
+        let mut rewrapped = updated.to_header();
+        rewrapped.extend_from_slice(b"new contents");
+        let (unwrapped, found) = unwrap_in_chunks(&rewrapped, 5).unwrap();
+        assert_eq!(unwrapped, b"new contents");
+        let found = found.unwrap();
+        assert_eq!(
+            (found.name, found.mode),
+            (envelope.name.clone(), envelope.mode)
+        );
+
+        // changing the contents is caught by the digest
+        let last = wrapped.len() - 1;
+        wrapped[last] ^= 1;
+        assert!(unwrap_in_chunks(&wrapped, 4).is_err());
+    }
+
+    #[test]
+    fn plaintext_without_envelope_passes_through() {
+        for bytes in [&b""[..], b"IH", b"plain contents"] {
+            let (unwrapped, found) = unwrap_in_chunks(bytes, 2).unwrap();
+            assert_eq!(unwrapped, bytes);
+            assert!(found.is_none());
+        }
+    }
+
+    #[test]
+    fn restores_read_only_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("restored");
+        fs::write(&path, b"contents").unwrap();
+        let envelope = Envelope {
+            name: None,
+            mode: Some(0o400),
+            modified: Some(1_700_000_000),
+            sha256: String::new(),
+        };
+        envelope.restore(&path).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.modified().ok(), envelope.modified_time());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o400);
+        }
+    }
+
+    #[test]
+    fn safe_name_rejects_paths() {
+        let with_name = |name: &str| Envelope {
+            name: Some(name.to_string()),
+            mode: None,
+            modified: None,
+            sha256: String::new(),
+        };
+        assert_eq!(with_name("a.txt").safe_name(), Some("a.txt"));
+        assert_eq!(with_name("../a.txt").safe_name(), None);
+        assert_eq!(with_name("dir/a.txt").safe_name(), None);
+        assert_eq!(with_name("/a.txt").safe_name(), None);
+        assert_eq!(with_name("..").safe_name(), None);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::util;
+use super::is_encrypted_file;
+use crate::{filter::FilterArgs, jobs::JobsArgs, util};
 use clap::Parser;
 use ironoxide::prelude::{BlockingIronOxide, GroupId, GroupName, UserId};
 use itertools::Either;
@@ -15,6 +16,12 @@ Add decrypt access to the specified file to multiple users and groups.\n
 Add decrypt access to all of the '.iron' files in the current directory to 'myGroup'.\n
     $ ironhide file grant -g myGroup *.iron
 
+Add decrypt access to every encrypted file under 'encrypted/' to 'myGroup'.\n
+    $ ironhide file grant -r -g myGroup encrypted/
+
+Give the specified file the same access as an existing encrypted file.\n
+    $ ironhide file grant --same-access-as path/to/other.iron path/to/file.iron
+
 ";
 
 #[derive(Parser)]
@@ -24,6 +31,13 @@ pub struct Grant {
     /// Path of file or files to grant access to.
     #[clap(value_parser = clap::value_parser!(PathBuf), num_args = 1.., required = true)]
     files: Vec<PathBuf>,
+    /// Grant access to every encrypted file in the provided directories and their subdirectories, whatever its suffix.
+    #[clap(short, long, num_args = 0)]
+    recursive: bool,
+    /// Also grant access to every user and group who can decrypt the given encrypted file. Anyone who couldn't be
+    /// granted access is listed under 'Failed Grants'.
+    #[clap(value_parser = clap::value_parser!(PathBuf), long)]
+    same_access_as: Option<PathBuf>,
     /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
     /// '~/.iron' directory.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
@@ -35,6 +49,10 @@ pub struct Grant {
     /// Grant access to the file(s) to a comma separated list of user emails.
     #[clap(value_parser = util::try_from_email, short, long, use_value_delimiter = true, value_delimiter = ',', required = false)]
     users: Vec<UserId>,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
 }
 
 impl util::GetKeyfile for Grant {
@@ -43,11 +61,18 @@ impl util::GetKeyfile for Grant {
     }
 }
 
-pub fn grant_files(sdk: &BlockingIronOxide, grant: Grant) -> Result<(), String> {
+pub fn grant_files(sdk: &BlockingIronOxide, mut grant: Grant) -> Result<(), String> {
+    if let Some(reference) = &grant.same_access_as {
+        util::add_access_from(sdk, reference, &mut grant.users, &mut grant.groups)?;
+    }
+    let files = util::collect_files(&grant.files, grant.recursive, &grant.filter, |path| {
+        is_encrypted_file(sdk, path)
+    })?;
     let grant_results = util::execute_permissioning_operation(
         &grant.users,
         &grant.groups,
-        &grant.files,
+        &files,
+        grant.jobs.count(),
         sdk,
         util::PermissionOperation::Grant,
     );
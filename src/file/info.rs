@@ -1,14 +1,26 @@
-use crate::util;
+use super::{bundle, decrypt, is_encrypted_file};
+use crate::{
+    filter::FilterArgs,
+    jobs::{self, JobsArgs},
+    util,
+};
 use clap::Parser;
 use ironoxide::prelude::BlockingIronOxide;
 use itertools::Itertools;
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 use yansi::Paint;
 
 const EXAMPLES: &str = "EXAMPLES
 
     $ ironhide file info path/to/file
     $ ironhide file info *.iron
+    $ ironhide file info -r path/to/dir
+    $ ironhide file info -r path/to/dir --jobs 8
+    $ ironhide file info --list config.iron
+    $ ironhide file info --metadata deploy.sh.iron
 
 ";
 
@@ -19,10 +31,25 @@ pub struct Info {
     /// Path of file or files to display information for.
     #[clap(value_parser = clap::value_parser!(PathBuf), num_args = 1.., required = true)]
     files: Vec<PathBuf>,
+    /// Display information for every encrypted file in the provided directories and their subdirectories, whatever its
+    /// suffix.
+    #[clap(short, long, num_args = 0)]
+    recursive: bool,
+    /// Decrypt each file and, if it's a bundle created with 'file encrypt --bundle', list the files it contains.
+    #[clap(long, num_args = 0)]
+    list: bool,
+    /// Decrypt each file and show the original name, permissions, modification time and SHA-256 stored by
+    /// 'file encrypt --preserve'.
+    #[clap(long, num_args = 0)]
+    metadata: bool,
     /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
     /// '~/.iron' directory.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
     keyfile: Option<PathBuf>,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
 }
 
 impl util::GetKeyfile for Info {
@@ -33,10 +60,43 @@ impl util::GetKeyfile for Info {
 
 pub fn investigate_files(sdk: &BlockingIronOxide, info: Info) -> Result<(), String> {
     // collect up all the file metadata results
-    let (successes, failures) = get_files_info(sdk, info.files);
+    let files = util::collect_files(&info.files, info.recursive, &info.filter, |path| {
+        is_encrypted_file(sdk, path)
+    })?;
+    let (successes, failures) = get_files_info(sdk, &files, info.jobs.count());
     let table = build_result_table(successes);
     table.printstd();
 
+    if info.list {
+        let tables = jobs::run(&files, info.jobs.count(), "listed", |path| {
+            list_bundle(sdk, path)
+        });
+        for (path, table) in files.iter().zip(tables) {
+            match table {
+                Ok(table) => {
+                    println!("\nContents of {}:", path.display());
+                    table.printstd();
+                }
+                Err(e) => println!("{}", Paint::red(e)),
+            }
+        }
+    }
+
+    if info.metadata {
+        let tables = jobs::run(&files, info.jobs.count(), "decrypted", |path| {
+            show_envelope(sdk, path)
+        });
+        for (path, table) in files.iter().zip(tables) {
+            match table {
+                Ok(table) => {
+                    println!("\nStored metadata for {}:", path.display());
+                    table.printstd();
+                }
+                Err(e) => println!("{}", Paint::red(e)),
+            }
+        }
+    }
+
     for fail in failures {
         println!("{}", fail);
     }
@@ -46,49 +106,43 @@ pub fn investigate_files(sdk: &BlockingIronOxide, info: Info) -> Result<(), Stri
 
 fn get_files_info(
     sdk: &BlockingIronOxide,
-    files: Vec<PathBuf>,
+    files: &[PathBuf],
+    jobs: usize,
 ) -> (
     Vec<(String, ironoxide::document::DocumentMetadataResult)>,
     Vec<Paint<String>>,
 ) {
-    files
-        .into_iter()
-        .map(|path| {
-            let mut file = File::open(&path).unwrap();
-            let mut encrypted_document = Vec::new();
-            file.read_to_end(&mut encrypted_document).unwrap();
-            let file_name = path.file_name().unwrap().to_string_lossy();
-
-            sdk.document_get_id_from_bytes(&encrypted_document)
-                .map_err(|e| match e {
-                    ironoxide::IronOxideErr::DocumentHeaderParseFailure(_) => Paint::red(format!(
-                        "Failed to parse '{}'. File doesn't appear to be an encrypted file.",
-                        file_name
-                    )),
-                    _ => Paint::red(format!(
-                        "Failed to get the document id for {}: {}.",
-                        file_name, e
-                    )),
-                })
-                .and_then(|id| {
-                    sdk.document_get_metadata(&id)
-                        .map(|metadata| (file_name.to_string(), metadata))
-                        .map_err(|e| {
-                            Paint::red(format!("Failed to get metadata for {}: {}.", file_name, e))
-                        })
+    jobs::run(files, jobs, "read", |path| {
+        // the whole path is shown, as files found with '-r' in different directories can share a name
+        util::get_document_id(sdk, path).and_then(|id| {
+            sdk.document_get_metadata(&id)
+                .map(|metadata| (path.display().to_string(), metadata))
+                .map_err(|e| {
+                    Paint::red(format!(
+                        "Failed to get metadata for {}: {}.",
+                        path.display(),
+                        e
+                    ))
                 })
         })
-        // pass back up the vec of path and metadata, along with the vec of failure messages.
-        .partition_result()
+    })
+    .into_iter()
+    // pass back up the vec of path and metadata, along with the vec of failure messages.
+    .partition_result()
 }
 
 fn build_result_table(
     metadata_pairs: Vec<(String, ironoxide::document::DocumentMetadataResult)>,
 ) -> prettytable::Table {
-    let mut table = table!([Fbb->"File", Fbb->"Users with access", Fbb->"Groups with access", Fbb->"Created", Fbb->"Updated"]);
+    let mut table = table!([Fbb->"File", Fbb->"Name", Fbb->"Document ID", Fbb->"Users with access", Fbb->"Groups with access", Fbb->"Created", Fbb->"Updated"]);
     for (path, metadata) in metadata_pairs {
         table.add_row(row![
             path,
+            metadata
+                .name()
+                .map(|name| name.name().as_str())
+                .unwrap_or("UNNAMED"),
+            metadata.id().id(),
             metadata
                 .visible_to_users()
                 .iter()
@@ -114,3 +168,47 @@ fn build_result_table(
     }
     table
 }
+
+/// Decrypt the file at `path` and build a table of the files in it if it's a bundle.
+fn list_bundle(sdk: &BlockingIronOxide, path: &Path) -> Result<prettytable::Table, String> {
+    let decrypted = decrypt::decrypt_to_memory(sdk, path)?;
+    if !bundle::is_bundle(&decrypted) {
+        return Err(format!("'{}' isn't a bundle.", path.display()));
+    }
+    let mut table = table!([Fbb->"Path", Fbb->"Size", Fbb->"Mode"]);
+    for entry in bundle::list(&decrypted)? {
+        let path = if entry.is_dir {
+            format!("{}/", entry.path.display())
+        } else {
+            entry.path.display().to_string()
+        };
+        table.add_row(row![path, r->entry.size, format!("{:o}", entry.mode & 0o777)]);
+    }
+    Ok(table)
+}
+
+/// Decrypt the file at `path` and build a table of the metadata stored in its envelope.
+fn show_envelope(sdk: &BlockingIronOxide, path: &Path) -> Result<prettytable::Table, String> {
+    let envelope = decrypt::decrypt_to(sdk, path, &mut io::sink())?
+        .envelope
+        .ok_or_else(|| {
+            format!(
+                "'{}' has no stored metadata. Encrypt it with '--preserve' to store it.",
+                path.display()
+            )
+        })?;
+    let mut table = table!([Fbb->"Original name", Fbb->"Mode", Fbb->"Modified", Fbb->"SHA-256"]);
+    table.add_row(row![
+        envelope.name.as_deref().unwrap_or("UNKNOWN"),
+        envelope
+            .mode
+            .map(|mode| format!("{mode:o}"))
+            .unwrap_or_else(|| "UNKNOWN".to_string()),
+        envelope
+            .modified_time()
+            .map(|modified| util::time_format(&modified.into()))
+            .unwrap_or_else(|| "UNKNOWN".to_string()),
+        envelope.sha256,
+    ]);
+    Ok(table)
+}
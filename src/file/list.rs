@@ -0,0 +1,157 @@
+use crate::util;
+use clap::Parser;
+use ironoxide::{
+    document::{AssociationType, DocumentListMeta},
+    prelude::{BlockingIronOxide, GroupId, GroupName},
+};
+use itertools::Either;
+use prettytable::Row;
+use std::path::PathBuf;
+use time::{Date, Month};
+
+const EXAMPLES: &str = "EXAMPLES
+
+    List every document you can decrypt.
+        $ ironhide file list
+
+    List the documents with 'prod' in their name that were updated this year.
+        $ ironhide file list --name prod --since 2024-01-01
+
+    List the documents shared with the 'ops' group.
+        $ ironhide file list --group ops
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Display a list of every document you can decrypt, and how you have access to each one.
+pub struct List {
+    /// Only show documents whose name contains the given text, ignoring case.
+    #[clap(short, long)]
+    name: Option<String>,
+    /// Only show documents shared with the given group. Can refer to a group by ID or by name. Indicate IDs by
+    /// prefixing with 'id^' e.g. 'id^groupID'. Looks up each document's access list, so it's slower for many documents.
+    #[clap(value_parser = util::group_identifier_from_string, short, long)]
+    group: Option<Either<GroupName, GroupId>>,
+    /// Only show documents last updated on or after the given date, as YYYY-MM-DD in UTC.
+    #[clap(value_parser = parse_date, long)]
+    since: Option<Date>,
+    /// Only show documents last updated on or before the given date, as YYYY-MM-DD in UTC.
+    #[clap(value_parser = parse_date, long)]
+    until: Option<Date>,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+}
+
+impl util::GetKeyfile for List {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+fn parse_date(s: &str) -> Result<Date, String> {
+    let invalid = || format!("Invalid date '{s}'. Dates should look like 2024-01-31.");
+    let (year, month, day) = match s.split('-').collect::<Vec<_>>().as_slice() {
+        [year, month, day] => (
+            year.parse::<i32>().map_err(|_| invalid())?,
+            month.parse::<u8>().map_err(|_| invalid())?,
+            day.parse::<u8>().map_err(|_| invalid())?,
+        ),
+        _ => return Err(invalid()),
+    };
+    let month = Month::try_from(month).map_err(|_| invalid())?;
+    Date::from_calendar_date(year, month, day).map_err(|_| invalid())
+}
+
+pub fn list_files(sdk: &BlockingIronOxide, list: List) -> Result<(), String> {
+    let documents = sdk
+        .document_list()
+        .map_err(|e| format!("Failed to list documents: {e}"))?;
+    let matching = documents
+        .result()
+        .iter()
+        .filter(|document| matches_filters(&list, document))
+        .filter(|document| match &list.group {
+            Some(group) => shared_with_group(sdk, document, group),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if matching.is_empty() {
+        Err("No documents you can decrypt match.".to_string())
+    } else {
+        let mut table = table!([Fbb=>"Document ID", "Name", "Access", "Created", "Updated"]);
+        for document in matching {
+            table.add_row(Row::new(vec![
+                cell!(Fw -> document.id().id()),
+                cell!(Fw -> document.name().map(|n| n.name().as_str()).unwrap_or("UNNAMED")),
+                cell!(Fw -> association(document.association_type())),
+                cell!(Fw -> util::time_format(document.created())),
+                cell!(Fw -> util::time_format(document.last_updated())),
+            ]));
+        }
+
+        table.printstd();
+
+        Ok(())
+    }
+}
+
+/// Whether the document passes the name and date filters, which don't need any more information from the service.
+fn matches_filters(list: &List, document: &DocumentListMeta) -> bool {
+    let name_matches = list.name.as_ref().is_none_or(|filter| {
+        document
+            .name()
+            .is_some_and(|name| name.name().to_lowercase().contains(&filter.to_lowercase()))
+    });
+    let updated = document.last_updated().date();
+    let since_matches = list.since.is_none_or(|since| updated >= since);
+    let until_matches = list.until.is_none_or(|until| updated <= until);
+    name_matches && since_matches && until_matches
+}
+
+/// Whether the document has been shared with `group`. Documents whose access list can't be read are left out.
+fn shared_with_group(
+    sdk: &BlockingIronOxide,
+    document: &DocumentListMeta,
+    group: &Either<GroupName, GroupId>,
+) -> bool {
+    sdk.document_get_metadata(document.id())
+        .map(|metadata| {
+            metadata
+                .visible_to_groups()
+                .iter()
+                .any(|visible| match group {
+                    Either::Left(name) => visible.name() == Some(name),
+                    Either::Right(id) => visible.id() == id,
+                })
+        })
+        .unwrap_or(false)
+}
+
+fn association(association_type: &AssociationType) -> &'static str {
+    match association_type {
+        AssociationType::Owner => "Owner",
+        AssociationType::FromUser => "Shared with you",
+        AssociationType::FromGroup => "Shared with a group you're in",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(
+            parse_date("2024-02-29").unwrap(),
+            Date::from_calendar_date(2024, Month::February, 29).unwrap()
+        );
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("2024/01/01").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+}
@@ -1,16 +1,39 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
 
 use clap::Parser;
+use ironoxide::{
+    IronOxideErr,
+    prelude::{BlockingIronOxide, DocumentId},
+};
 
 use crate::util;
 
+pub mod armor;
+pub mod bundle;
+pub mod compress;
 pub mod decrypt;
+pub mod edit;
 pub mod encrypt;
+pub mod envelope;
 pub mod grant;
 pub mod info;
+pub mod list;
+pub mod naming;
+pub mod output;
+pub mod rekey;
+pub mod rename;
 pub mod revoke;
+pub mod stream;
+pub mod unmanaged;
+pub mod update;
+pub mod verify;
 
-/// Encrypt and decrypt files, display information about encrypted files, and grant or revoke access to encrypted files.
+/// Encrypt and decrypt files, display information about encrypted files, list the documents you can decrypt, edit,
+/// rename, update, re-key or verify encrypted files, and grant or revoke access to encrypted files.
 #[derive(Parser)]
 pub struct File {
     #[clap(subcommand)]
@@ -21,10 +44,16 @@ impl util::GetKeyfile for File {
     fn get_keyfile(&self) -> Option<&PathBuf> {
         match &self.subcmd {
             FileSubcommands::Decrypt(decrypt) => decrypt.get_keyfile(),
+            FileSubcommands::Edit(edit) => edit.get_keyfile(),
             FileSubcommands::Encrypt(encrypt) => encrypt.get_keyfile(),
             FileSubcommands::Grant(grant) => grant.get_keyfile(),
             FileSubcommands::Info(info) => info.get_keyfile(),
+            FileSubcommands::List(list) => list.get_keyfile(),
+            FileSubcommands::Rekey(rekey) => rekey.get_keyfile(),
+            FileSubcommands::Rename(rename) => rename.get_keyfile(),
             FileSubcommands::Revoke(revoke) => revoke.get_keyfile(),
+            FileSubcommands::Update(update) => update.get_keyfile(),
+            FileSubcommands::Verify(verify) => verify.get_keyfile(),
         }
     }
 }
@@ -33,12 +62,73 @@ impl util::GetKeyfile for File {
 pub enum FileSubcommands {
     #[clap(name = "decrypt")]
     Decrypt(decrypt::Decrypt),
+    #[clap(name = "edit")]
+    Edit(edit::Edit),
     #[clap(name = "encrypt")]
-    Encrypt(encrypt::Encrypt),
+    Encrypt(Box<encrypt::Encrypt>),
     #[clap(name = "info")]
     Info(info::Info),
     #[clap(name = "grant")]
     Grant(grant::Grant),
+    #[clap(name = "list")]
+    List(list::List),
+    #[clap(name = "rekey")]
+    Rekey(rekey::Rekey),
+    #[clap(name = "rename")]
+    Rename(rename::Rename),
     #[clap(name = "revoke")]
     Revoke(revoke::Revoke),
+    #[clap(name = "update")]
+    Update(update::Update),
+    #[clap(name = "verify")]
+    Verify(verify::Verify),
+}
+
+/// Length of the version byte and big-endian u16 header length at the start of every IronCore document.
+const DOCUMENT_PREFIX_LEN: usize = 3;
+/// Enough of a document to hold the longest possible header, followed by the shortest possible encrypted value.
+const MAX_ID_BYTES: u64 = (DOCUMENT_PREFIX_LEN + u16::MAX as usize + 28) as u64;
+
+/// Read the bytes of `path` that identify its IronCore document, suitable for `document_id_from_bytes`. Armored
+/// files are decoded first. Only the start of a document is read, and for stream containers only the embedded key
+/// document, so large files aren't read into memory. Unmanaged files are an error, as the service has no document to
+/// look up.
+pub fn read_id_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    let (is_stream, mut reader) = stream::sniff(armor::dearmor(fs::File::open(path)?)?)?;
+    if is_stream {
+        stream::read_key_document(&mut reader)
+    } else {
+        let mut bytes = Vec::new();
+        reader.take(MAX_ID_BYTES).read_to_end(&mut bytes)?;
+        if unmanaged::is_unmanaged(&bytes) {
+            return Err(unmanaged::not_registered());
+        }
+        Ok(bytes)
+    }
+}
+
+/// Get the document ID from the start of an encrypted document. `document_get_id_from_bytes` panics when there are
+/// fewer bytes than the header says there should be, so that's checked first.
+pub fn document_id_from_bytes(
+    sdk: &BlockingIronOxide,
+    bytes: &[u8],
+) -> Result<DocumentId, IronOxideErr> {
+    let has_header = bytes.len() >= DOCUMENT_PREFIX_LEN
+        && bytes.len() >= DOCUMENT_PREFIX_LEN + u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+    if has_header {
+        sdk.document_get_id_from_bytes(bytes)
+    } else {
+        Err(IronOxideErr::DocumentHeaderParseFailure(
+            "Document is too short to be an encrypted file.".to_string(),
+        ))
+    }
+}
+
+/// Whether the file at `path` is already encrypted, going by its header.
+pub fn is_encrypted_file(sdk: &BlockingIronOxide, path: &Path) -> bool {
+    match read_id_bytes(path) {
+        Ok(bytes) => document_id_from_bytes(sdk, &bytes).is_ok(),
+        // only unmanaged files can't be looked up
+        Err(e) => e.kind() == io::ErrorKind::Unsupported,
+    }
 }
@@ -0,0 +1,264 @@
+//! How encrypt and decrypt name their output files.
+//!
+//! Encrypted files are named by adding a suffix, '.iron' by default, to the input's file name. Decrypting only ever
+//! removes that suffix. An input without it is decrypted to the original name stored with the document when one is
+//! known, and otherwise gets '.decrypted' added, so the output never silently drops part of the input's name or lands
+//! on top of the input. '--output-template' replaces all of these rules with an explicit pattern.
+
+use clap::Args;
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Component, Path},
+};
+
+/// Added to the names of decrypted files that don't end with the encrypted suffix.
+const DECRYPTED_SUFFIX: &str = ".decrypted";
+
+/// Flags controlling the names of output files.
+#[derive(Args)]
+pub struct NamingArgs {
+    /// Suffix marking encrypted files. It's added to the names of encrypted files and removed from the names of
+    /// decrypted ones. With '-r', only files with this suffix are decrypted and files that already have it aren't
+    /// encrypted again.
+    #[clap(long, value_parser = parse_suffix, default_value = ".iron")]
+    suffix: String,
+    /// Pattern for the names of output files, used instead of the suffix. '{name}' is replaced with the input's file
+    /// name, '{stem}' with the input's file name without its last extension, and '{ext}' with its last extension.
+    /// The output is written in the same directory it would be otherwise.
+    #[clap(long, value_parser = parse_template)]
+    output_template: Option<String>,
+}
+
+fn parse_suffix(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(std::path::is_separator) {
+        Err("Suffix must be non-empty and can't contain path separators.".to_string())
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+fn parse_template(s: &str) -> Result<String, String> {
+    if !s.contains(['{', '}']) {
+        Err("Template must contain at least one of '{name}', '{stem}' or '{ext}'.".to_string())
+    } else if s.contains(std::path::is_separator) {
+        Err("Template can't contain path separators.".to_string())
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+impl NamingArgs {
+    /// Whether output names were chosen with '--output-template'.
+    pub fn has_template(&self) -> bool {
+        self.output_template.is_some()
+    }
+
+    /// Whether `path` is named like an encrypted file.
+    pub fn has_suffix(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|name| self.without_suffix(name).is_some())
+    }
+
+    /// `name` with the suffix removed, if it ends with the suffix and has something before it. Works on the raw bytes so
+    /// names that aren't valid UTF-8 are kept intact.
+    fn without_suffix<'a>(&self, name: &'a OsStr) -> Option<&'a OsStr> {
+        let stem = name
+            .as_encoded_bytes()
+            .strip_suffix(self.suffix.as_bytes())
+            .filter(|stem| !stem.is_empty())?;
+        // SAFETY: `stem` is `name` with a non-empty UTF-8 string removed from its end, so it's split on a boundary that
+        // `from_encoded_bytes_unchecked` allows
+        Some(unsafe { OsStr::from_encoded_bytes_unchecked(stem) })
+    }
+
+    /// File name of the encrypted output for the input at `path`.
+    pub fn encrypted_name(&self, path: &Path) -> Result<OsString, String> {
+        let name = file_name(path)?;
+        match &self.output_template {
+            Some(template) => apply_template(template, path),
+            None => {
+                let mut name = name.to_os_string();
+                name.push(&self.suffix);
+                Ok(name)
+            }
+        }
+    }
+
+    /// File name of the decrypted output for the encrypted file at `path`. `stored_name` looks up the original name
+    /// stored with the document, and is only called for inputs that don't end with the suffix.
+    pub fn decrypted_name(
+        &self,
+        path: &Path,
+        stored_name: impl FnOnce() -> Option<String>,
+    ) -> Result<OsString, String> {
+        let name = file_name(path)?;
+        if let Some(template) = &self.output_template {
+            return apply_template(template, path);
+        }
+        if let Some(stem) = self.without_suffix(name) {
+            return Ok(stem.to_os_string());
+        }
+        match stored_name() {
+            Some(stored) if safe_file_name(&stored).is_some() && name != stored.as_str() => {
+                Ok(stored.into())
+            }
+            _ => {
+                let mut name = name.to_os_string();
+                name.push(DECRYPTED_SUFFIX);
+                Ok(name)
+            }
+        }
+    }
+}
+
+/// `name` if it's a plain file name that can't point outside of the directory it's written into.
+pub fn safe_file_name(name: &str) -> Option<&str> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Some(name),
+        _ => None,
+    }
+}
+
+fn file_name(path: &Path) -> Result<&OsStr, String> {
+    path.file_name()
+        .ok_or_else(|| format!("Invalid input file '{}'", path.display()))
+}
+
+fn apply_template(template: &str, path: &Path) -> Result<OsString, String> {
+    let name = file_name(path)?.to_string_lossy();
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (name.as_ref(), ""),
+    };
+    let output = template
+        .replace("{name}", &name)
+        .replace("{stem}", stem)
+        .replace("{ext}", ext);
+    safe_file_name(&output).map(OsString::from).ok_or_else(|| {
+        format!(
+            "Output template '{template}' gives '{output}' for '{}', which isn't a valid file name.",
+            path.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(suffix: &str, output_template: Option<&str>) -> NamingArgs {
+        NamingArgs {
+            suffix: suffix.to_string(),
+            output_template: output_template.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn encrypted_and_decrypted_names() {
+        let default = naming(".iron", None);
+        let no_stored_name = || None;
+        assert_eq!(
+            default.encrypted_name(Path::new("dir/a.tar.gz")).unwrap(),
+            "a.tar.gz.iron"
+        );
+        assert_eq!(
+            default
+                .decrypted_name(Path::new("dir/a.tar.gz.iron"), no_stored_name)
+                .unwrap(),
+            "a.tar.gz"
+        );
+        // only the suffix is ever removed
+        assert_eq!(
+            default
+                .decrypted_name(Path::new("secret.tar.gz"), no_stored_name)
+                .unwrap(),
+            "secret.tar.gz.decrypted"
+        );
+        assert_eq!(
+            default
+                .decrypted_name(Path::new(".iron"), no_stored_name)
+                .unwrap(),
+            ".iron.decrypted"
+        );
+        // a stored name is used, unless it would be written over the input or outside its directory
+        let stored = |name: &str| {
+            let name = name.to_string();
+            move || Some(name)
+        };
+        assert_eq!(
+            default
+                .decrypted_name(Path::new("config"), stored("config.json"))
+                .unwrap(),
+            "config.json"
+        );
+        assert_eq!(
+            default
+                .decrypted_name(Path::new("config"), stored("config"))
+                .unwrap(),
+            "config.decrypted"
+        );
+        assert_eq!(
+            default
+                .decrypted_name(Path::new("config"), stored("../config"))
+                .unwrap(),
+            "config.decrypted"
+        );
+
+        let enc = naming(".enc", None);
+        assert_eq!(enc.encrypted_name(Path::new("a.txt")).unwrap(), "a.txt.enc");
+        assert_eq!(
+            enc.decrypted_name(Path::new("a.txt.enc"), no_stored_name)
+                .unwrap(),
+            "a.txt"
+        );
+        assert!(enc.has_suffix(Path::new("dir/a.enc")));
+        assert!(!enc.has_suffix(Path::new("a.iron")));
+
+        // names that aren't valid UTF-8 only lose the suffix
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let name = OsStr::from_bytes(b"caf\xe9.txt.iron");
+            assert!(default.has_suffix(Path::new(name)));
+            assert_eq!(
+                default
+                    .decrypted_name(Path::new(name), no_stored_name)
+                    .unwrap(),
+                OsStr::from_bytes(b"caf\xe9.txt")
+            );
+        }
+    }
+
+    #[test]
+    fn output_templates() {
+        let template = naming(".iron", Some("{stem}-encrypted.{ext}"));
+        assert_eq!(
+            template
+                .encrypted_name(Path::new("dir/report.pdf"))
+                .unwrap(),
+            "report-encrypted.pdf"
+        );
+        let template = naming(".iron", Some("decrypted-{stem}"));
+        assert_eq!(
+            template
+                .decrypted_name(Path::new("a.txt.iron"), || None)
+                .unwrap(),
+            "decrypted-a.txt"
+        );
+        assert_eq!(
+            naming(".iron", Some("{name}.bin"))
+                .encrypted_name(Path::new(".env"))
+                .unwrap(),
+            ".env.bin"
+        );
+        assert!(
+            naming(".iron", Some("{ext}"))
+                .encrypted_name(Path::new("noext"))
+                .is_err()
+        );
+        assert!(parse_template("plain").is_err());
+        assert!(parse_template("dir/{name}").is_err());
+        assert!(parse_suffix("").is_err());
+    }
+}
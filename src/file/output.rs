@@ -0,0 +1,294 @@
+//! Output files written by encrypt and decrypt, and what happens when one already exists.
+//!
+//! Every output is written to a temporary file in the destination directory, which is synced to disk and then renamed
+//! into place once it's complete. If the process is killed part way through, the destination is either untouched or
+//! holds the complete output, never a truncated file that looks valid.
+//!
+//! By default an existing file is never touched and the write fails. `--force` and `--backup` rename the new output over
+//! the existing file. `--skip-existing` leaves existing files alone and skips the inputs they'd be written from.
+
+use clap::Args;
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+use yansi::Paint;
+
+use crate::util;
+
+/// Extension added to the name of an existing file when it's moved aside by '--backup'.
+const BACKUP_EXTENSION: &str = "bak";
+
+/// Flags controlling what happens when an output file already exists.
+#[derive(Args)]
+pub struct OverwriteArgs {
+    /// Replace output files that already exist.
+    #[clap(long, num_args = 0, conflicts_with_all = ["skip_existing", "backup"])]
+    force: bool,
+    /// Leave output files that already exist untouched and skip the inputs they would have been written from.
+    #[clap(long, num_args = 0, conflicts_with = "backup")]
+    skip_existing: bool,
+    /// Replace output files that already exist, first renaming each one to the same name with '.bak' added. If that backup
+    /// already exists, '.bak.1', '.bak.2' and so on are used instead, so earlier backups are never replaced.
+    #[clap(long, num_args = 0)]
+    backup: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Never,
+    Force,
+    SkipExisting,
+    Backup,
+}
+
+impl OverwriteArgs {
+    pub fn policy(&self) -> Overwrite {
+        if self.force {
+            Overwrite::Force
+        } else if self.skip_existing {
+            Overwrite::SkipExisting
+        } else if self.backup {
+            Overwrite::Backup
+        } else {
+            Overwrite::Never
+        }
+    }
+}
+
+/// Whether `path` represents stdout rather than a file.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Check whether writing to `path` should be skipped because it exists and '--skip-existing' was given. Prints a note
+/// about the skipped file, so callers only need to return early.
+pub fn should_skip(path: &Path, overwrite: Overwrite) -> bool {
+    let skip = overwrite == Overwrite::SkipExisting && !is_stdout(path) && path.exists();
+    if skip {
+        util::println_paint(Paint::yellow(format!(
+            "Skipped writing '{}' as it already exists.",
+            path.display()
+        )));
+    }
+    skip
+}
+
+/// A file being written by encrypt or decrypt. Nothing is written to the final path until `finish` is called, and the
+/// partial output is removed if this is dropped first.
+pub struct OutputFile {
+    path: PathBuf,
+    target: Target,
+}
+
+enum Target {
+    Stdout(io::Stdout),
+    File {
+        temp: NamedTempFile,
+        overwrite: Overwrite,
+    },
+}
+
+impl OutputFile {
+    /// Open `path` for writing, where '-' is stdout. Fails if the file exists, unless `overwrite` allows replacing it.
+    pub fn create(path: &Path, overwrite: Overwrite) -> Result<Self, String> {
+        let create_err = |e: io::Error| {
+            format!(
+                "Couldn't create a file at the output path '{}': {e}",
+                path.display()
+            )
+        };
+        let target = if is_stdout(path) {
+            Target::Stdout(io::stdout())
+        } else {
+            // fail before any work is done, although it's checked again when the output is moved into place
+            if !replaces_existing(overwrite) && path.exists() {
+                return Err(create_err(io::ErrorKind::AlreadyExists.into()));
+            }
+            Target::File {
+                temp: new_temp_file(parent_dir(path)).map_err(create_err)?,
+                overwrite,
+            }
+        };
+        Ok(OutputFile {
+            path: path.to_path_buf(),
+            target,
+        })
+    }
+
+    /// How to describe where the output went.
+    pub fn log_path(&self) -> String {
+        match self.target {
+            Target::Stdout(_) => "stdout".to_string(),
+            _ => self.path.display().to_string(),
+        }
+    }
+
+    /// Change where the output is moved to by `finish`. The new path has to be in the same directory as the original,
+    /// since that's where the output is being written. Has no effect when writing to stdout.
+    pub fn set_path(&mut self, path: PathBuf) {
+        if let Target::File { .. } = self.target {
+            debug_assert_eq!(parent_dir(&path), parent_dir(&self.path));
+            self.path = path;
+        }
+    }
+
+    /// Finish writing, syncing the output to disk and moving it into place.
+    pub fn finish(mut self) -> Result<(), String> {
+        let write_err =
+            |path: &Path, e: io::Error| format!("Couldn't write '{}': {e}", path.display());
+        self.flush().map_err(|e| write_err(&self.path, e))?;
+        let OutputFile { path, target } = self;
+        if let Target::File { temp, overwrite } = target {
+            if replaces_existing(overwrite) {
+                // the replacement keeps the permissions of the file it replaces
+                if let Ok(metadata) = fs::metadata(&path) {
+                    fs::set_permissions(temp.path(), metadata.permissions())
+                        .map_err(|e| write_err(&path, e))?;
+                }
+            }
+            temp.as_file().sync_all().map_err(|e| write_err(&path, e))?;
+            if overwrite == Overwrite::Backup && path.exists() {
+                let backup_path = backup_path(&path);
+                fs::rename(&path, &backup_path).map_err(|e| {
+                    format!(
+                        "Couldn't back up '{}' to '{}': {e}",
+                        path.display(),
+                        backup_path.display()
+                    )
+                })?;
+            }
+            if replaces_existing(overwrite) {
+                temp.persist(&path).map_err(|e| write_err(&path, e.error))?;
+            } else {
+                temp.persist_noclobber(&path)
+                    .map_err(|e| write_err(&path, e.error))?;
+            }
+            sync_dir(parent_dir(&path)).map_err(|e| write_err(&path, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            Target::Stdout(stdout) => stdout.write(buf),
+            Target::File { temp, .. } => temp.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            Target::Stdout(stdout) => stdout.flush(),
+            Target::File { temp, .. } => temp.flush(),
+        }
+    }
+}
+
+fn replaces_existing(overwrite: Overwrite) -> bool {
+    matches!(overwrite, Overwrite::Force | Overwrite::Backup)
+}
+
+/// Sync a directory so that a rename inside it survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Temporary files are normally only readable by their owner. Ask for the same permissions a new file would get
+/// instead, since the file is going to become the output.
+fn new_temp_file(dir: &Path) -> io::Result<NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // the umask still applies, as it does for `OpenOptions`
+        builder.permissions(fs::Permissions::from_mode(0o666));
+    }
+    builder.tempfile_in(dir)
+}
+
+/// Directory that holds `path`, which is where temporary files have to go for the rename into place to be atomic.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// The first of '<file>.bak', '<file>.bak.1', '<file>.bak.2', ... that doesn't exist yet, so earlier backups are kept.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut base = OsString::from(path.as_os_str());
+    base.push(".");
+    base.push(BACKUP_EXTENSION);
+    (0..)
+        .map(|n| {
+            let mut backup = base.clone();
+            if n > 0 {
+                backup.push(format!(".{n}"));
+            }
+            PathBuf::from(backup)
+        })
+        // a dangling symlink still takes up the name
+        .find(|backup| fs::symlink_metadata(backup).is_err())
+        .expect("there's always an unused backup name")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, overwrite: Overwrite, contents: &str) -> Result<(), String> {
+        let mut output = OutputFile::create(path, overwrite)?;
+        output
+            .write_all(contents.as_bytes())
+            .map_err(|e| e.to_string())?;
+        output.finish()
+    }
+
+    #[test]
+    fn overwrite_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let path = root.join("file.iron");
+
+        write(&path, Overwrite::Never, "first").unwrap();
+        assert!(write(&path, Overwrite::Never, "second").is_err());
+        assert!(should_skip(&path, Overwrite::SkipExisting));
+        assert!(!should_skip(&path, Overwrite::Force));
+        assert!(!should_skip(Path::new("-"), Overwrite::SkipExisting));
+
+        write(&path, Overwrite::Force, "forced").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "forced");
+
+        let first_backup = backup_path(&path);
+        write(&path, Overwrite::Backup, "backed up").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "backed up");
+        assert_eq!(fs::read_to_string(&first_backup).unwrap(), "forced");
+
+        // backing up again keeps the earlier backup
+        write(&path, Overwrite::Backup, "backed up again").unwrap();
+        assert_eq!(fs::read_to_string(&first_backup).unwrap(), "forced");
+        assert_eq!(
+            fs::read_to_string(root.join("file.iron.bak.1")).unwrap(),
+            "backed up"
+        );
+        write(&path, Overwrite::Force, "backed up").unwrap();
+
+        // an unfinished replacement leaves the existing file alone
+        let mut output = OutputFile::create(&path, Overwrite::Force).unwrap();
+        output.write_all(b"partial").unwrap();
+        drop(output);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "backed up");
+        assert_eq!(fs::read_dir(root).unwrap().count(), 3);
+    }
+}
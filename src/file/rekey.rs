@@ -0,0 +1,191 @@
+use super::{
+    armor, compress,
+    decrypt::{self, Decrypted},
+    output::{OutputFile, Overwrite},
+    stream, update,
+};
+use crate::util;
+use clap::Parser;
+use ironoxide::prelude::*;
+use itertools::EitherOrBoth;
+use std::{
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+const EXAMPLES: &str = "EXAMPLES
+
+    $ ironhide file rekey path/to/file.iron
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Re-encrypt a file under a new document key, for example after a suspected key compromise. The new document is
+/// shared with exactly the users and groups who can decrypt the current one, and has the same name. Once the file has
+/// been replaced, everyone's access to the old document is revoked, and the command fails listing anyone whose access
+/// couldn't be. The file keeps its format, but gets a new document ID.
+pub struct Rekey {
+    /// Path of the encrypted file to re-key.
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    file: PathBuf,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+}
+
+impl util::GetKeyfile for Rekey {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+pub fn rekey_file(sdk: &BlockingIronOxide, Rekey { file, .. }: Rekey) -> Result<(), String> {
+    let old_id = util::get_document_id(sdk, &file).map_err(|e| e.inner().clone())?;
+    let metadata = sdk
+        .document_get_metadata(&old_id)
+        .map_err(|e| format!("Failed to get metadata for '{}': {e}", file.display()))?;
+    let access_list = metadata
+        .visible_to_users()
+        .iter()
+        .map(|user| UserOrGroup::User {
+            id: user.id().clone(),
+        })
+        .chain(
+            metadata
+                .visible_to_groups()
+                .iter()
+                .map(|group| UserOrGroup::Group {
+                    id: group.id().clone(),
+                }),
+        )
+        .collect::<Vec<_>>();
+
+    let (armored, is_stream) = update::existing_format(&file).map_err(|e| {
+        format!(
+            "Failed to read bytes from the encrypted document at {}: {e}",
+            file.display()
+        )
+    })?;
+    // the metadata envelope and compression, if there are any, are kept as they are since the contents aren't changing
+    let (decrypted, plaintext) = decrypt_plaintext(sdk, &file, is_stream)?;
+    let mut plaintext: Box<dyn Read> = match decrypted.envelope {
+        Some(envelope) => Box::new(Cursor::new(envelope.to_header()).chain(plaintext)),
+        None => plaintext,
+    };
+    if let Some(codec) = decrypted.codec {
+        plaintext = compress::compress(codec, plaintext)
+            .map_err(|e| format!("Couldn't compress data to encrypt: {e}"))?;
+    }
+    // the old access list is copied exactly, so the caller isn't added if they only had access through a group
+    let opts = DocumentEncryptOpts::new(
+        None,
+        metadata.name().cloned(),
+        EitherOrBoth::Left(ExplicitGrant::new(false, &access_list)),
+    );
+    let mut output = BufWriter::new(OutputFile::create(&file, Overwrite::Force)?);
+    let encrypt_result = armor::write_output(&mut output, armored, |writer| {
+        if is_stream {
+            stream::encrypt_stream(sdk, &opts, &mut plaintext, writer)
+        } else {
+            let mut contents = Vec::new();
+            plaintext
+                .read_to_end(&mut contents)
+                .map_err(|e| format!("Couldn't read data to encrypt: {e}"))?;
+            let encrypt_result = sdk.document_encrypt(contents, &opts)?;
+            writer
+                .write_all(encrypt_result.encrypted_data())
+                .map_err(|e| format!("Couldn't write encrypted file: {e}"))?;
+            Ok(encrypt_result)
+        }
+    })?;
+    // nothing has been replaced yet, so the old document still works for everyone
+    if !encrypt_result.access_errs().is_empty() {
+        return Err(format!(
+            "'{}' was not re-keyed as the new document couldn't be shared with {}.",
+            file.display(),
+            encrypt_result
+                .access_errs()
+                .iter()
+                .map(|access_err| describe(&access_err.user_or_group))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    output
+        .into_inner()
+        .map_err(|e| format!("Couldn't write encrypted file: {}", e.error()))?
+        .finish()?;
+    util::println_paint(Paint::green(format!(
+        "Encrypted file '{}' re-keyed with new document ID '{}'.",
+        file.display(),
+        encrypt_result.id().id()
+    )));
+
+    // the old document key may be compromised, so anyone still able to use it is an error
+    let revoke_result = sdk
+        .document_revoke_access(&old_id, &access_list)
+        .map_err(|e| {
+            format!(
+                "Failed to revoke access to the old document '{}', so these users and groups can still decrypt it: \
+                 {}. {e}",
+                old_id.id(),
+                access_list.iter().map(describe).collect::<Vec<_>>().join(", ")
+            )
+        })?;
+    if !revoke_result.failed().is_empty() {
+        return Err(format!(
+            "Couldn't revoke access to the old document '{}', so these users and groups can still decrypt it: {}",
+            old_id.id(),
+            revoke_result
+                .failed()
+                .iter()
+                .map(|access_err| {
+                    format!(
+                        "{} ({})",
+                        describe(&access_err.user_or_group),
+                        access_err.err
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    util::println_paint(Paint::green(format!(
+        "Revoked everyone's access to the old document '{}'.",
+        old_id.id()
+    )));
+    Ok(())
+}
+
+/// Decrypt `file` and return a reader over its plaintext. Streams can be too large to hold in memory, so they're
+/// decrypted into an anonymous temporary file that's removed as soon as it's closed.
+fn decrypt_plaintext(
+    sdk: &BlockingIronOxide,
+    file: &Path,
+    is_stream: bool,
+) -> Result<(Decrypted, Box<dyn Read>), String> {
+    if is_stream {
+        let spool_err =
+            |e: io::Error| format!("Couldn't write the decrypted file to a temporary file: {e}");
+        // tempfile creates files with 0600 permissions on unix
+        let mut spool = BufWriter::new(tempfile::tempfile().map_err(spool_err)?);
+        let decrypted = decrypt::decrypt_to(sdk, file, &mut spool)?;
+        let mut spool = spool.into_inner().map_err(|e| spool_err(e.into_error()))?;
+        spool.rewind().map_err(spool_err)?;
+        Ok((decrypted, Box::new(BufReader::new(spool))))
+    } else {
+        let mut plaintext = Vec::new();
+        let decrypted = decrypt::decrypt_to(sdk, file, &mut plaintext)?;
+        Ok((decrypted, Box::new(Cursor::new(plaintext))))
+    }
+}
+
+fn describe(user_or_group: &UserOrGroup) -> String {
+    match user_or_group {
+        UserOrGroup::User { id } => id.id().to_string(),
+        UserOrGroup::Group { id } => format!("id^{}", id.id()),
+    }
+}
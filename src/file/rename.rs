@@ -0,0 +1,61 @@
+use super::encrypt::parse_document_name;
+use crate::util;
+use clap::Parser;
+use ironoxide::prelude::{BlockingIronOxide, DocumentName};
+use std::path::PathBuf;
+use yansi::Paint;
+
+const EXAMPLES: &str = "EXAMPLES
+
+    $ ironhide file rename path/to/file.iron \"Q1 report\"
+    $ ironhide file rename path/to/file.iron --clear
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Change the name of an encrypted document. Won't change the file itself, its ID, or who has access to it.
+pub struct Rename {
+    /// Path of the encrypted file to rename.
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    file: PathBuf,
+    /// New name of the document.
+    #[clap(value_parser = parse_document_name, required_unless_present = "clear")]
+    new_name: Option<DocumentName>,
+    /// Remove the document's name instead of changing it.
+    #[clap(long, num_args = 0, conflicts_with = "new_name")]
+    clear: bool,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+}
+
+impl util::GetKeyfile for Rename {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+pub fn rename_file(
+    sdk: &BlockingIronOxide,
+    Rename { file, new_name, .. }: Rename,
+) -> Result<(), String> {
+    match util::get_document_id(sdk, &file) {
+        Ok(document_id) => match sdk.document_update_name(&document_id, new_name.as_ref()) {
+            Ok(_) => {
+                util::println_paint(Paint::green(
+                    "Document name successfully updated.".to_string(),
+                ));
+            }
+            Err(err) => {
+                util::println_paint(Paint::red(format!(
+                    "Document could not be updated: {}",
+                    err
+                )));
+            }
+        },
+        Err(err) => util::println_paint(err),
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use crate::util;
+use super::is_encrypted_file;
+use crate::{filter::FilterArgs, jobs::JobsArgs, util};
 use clap::Parser;
 use ironoxide::prelude::{BlockingIronOxide, GroupId, GroupName, UserId};
 use itertools::Either;
@@ -15,6 +16,9 @@ Revoke access to the provided encrypted file from the provided users and groups.
 Revoke access to all of the '.iron' files from 'myGroup'. 
     $ ironhide file revoke -g myGroup *.iron
 
+Revoke access to every encrypted file under 'encrypted/' from 'myGroup'. 
+    $ ironhide file revoke -r -g myGroup encrypted/
+
 ";
 
 #[derive(Parser)]
@@ -24,6 +28,9 @@ pub struct Revoke {
     /// Path of file or files to revoke access to.
     #[clap(value_parser = clap::value_parser!(PathBuf), num_args = 1.., required = true)]
     files: Vec<PathBuf>,
+    /// Revoke access to every encrypted file in the provided directories and their subdirectories, whatever its suffix.
+    #[clap(short, long, num_args = 0)]
+    recursive: bool,
     /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
     /// '~/.iron' directory.
     #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
@@ -35,6 +42,10 @@ pub struct Revoke {
     /// Revoke access to the file(s) to a comma separated list of user emails.
     #[clap(value_parser = util::try_from_email, short, long, use_value_delimiter = true, value_delimiter = ',', required = false)]
     users: Vec<UserId>,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
 }
 
 impl util::GetKeyfile for Revoke {
@@ -45,10 +56,14 @@ impl util::GetKeyfile for Revoke {
 
 // TODO: this function is very similar to grant::grant_files, should make more generic
 pub fn revoke_files(sdk: &BlockingIronOxide, revoke: Revoke) -> Result<(), String> {
+    let files = util::collect_files(&revoke.files, revoke.recursive, &revoke.filter, |path| {
+        is_encrypted_file(sdk, path)
+    })?;
     let revoke_results = util::execute_permissioning_operation(
         &revoke.users,
         &revoke.groups,
-        &revoke.files,
+        &files,
+        revoke.jobs.count(),
         sdk,
         util::PermissionOperation::Revoke,
     );
@@ -0,0 +1,266 @@
+//! Chunked container used to encrypt files too large to comfortably hold in memory.
+//!
+//! A stream file starts with `MAGIC` and a version byte, followed by a length-prefixed IronCore document whose
+//! plaintext is a random AES-256 key. The rest of the file is a sequence of length-prefixed segments, each holding at
+//! most `SEGMENT_SIZE` bytes of plaintext sealed with AES-GCM under that key. A segment's nonce is its position in the
+//! stream plus a flag marking the final segment, so reordered, dropped or truncated segments fail to decrypt.
+//!
+//! Access to the whole file is controlled by access to the embedded key document, which means grant, revoke and info
+//! all operate on the key document's ID.
+
+use ironoxide::prelude::*;
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::io::{self, BufRead, BufReader, Chain, Cursor, Read, Write};
+
+/// Leading bytes of every stream container. Managed IronCore documents always start with a version byte of 2, so the
+/// two can't be confused.
+const MAGIC: &[u8] = b"IHSTREAM";
+const VERSION: u8 = 1;
+/// Maximum number of plaintext bytes in a single segment.
+const SEGMENT_SIZE: usize = 1024 * 1024;
+/// Upper bound on the size of the embedded key document. Anything larger is treated as corruption rather than
+/// allocated.
+const MAX_KEY_DOCUMENT_SIZE: usize = 64 * 1024;
+const KEY_LEN: usize = 32;
+
+/// Reader that replays bytes already inspected by `sniff` before continuing with the rest of the input.
+pub type Sniffed<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Read the first few bytes of `reader` to decide whether it holds a stream container. The returned reader yields the
+/// complete input, including the bytes that were inspected.
+pub fn sniff<R: Read>(mut reader: R) -> io::Result<(bool, Sniffed<R>)> {
+    let mut prefix = Vec::with_capacity(MAGIC.len());
+    (&mut reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    Ok((prefix == MAGIC, Cursor::new(prefix).chain(reader)))
+}
+
+/// Encrypt everything readable from `reader` to `writer` as a stream container. The key document is encrypted with
+/// `opts`, and its encrypt result is returned so callers can report on the grants.
+pub fn encrypt_stream(
+    sdk: &BlockingIronOxide,
+    opts: &DocumentEncryptOpts,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<DocumentEncryptResult, String> {
+    let key_bytes = generate_key()?;
+    let key_document = sdk.document_encrypt(key_bytes.to_vec(), opts)?;
+    write_stream(&key_bytes, key_document.encrypted_data(), reader, writer)?;
+    Ok(key_document)
+}
+
+/// Encrypt everything readable from `reader` to `writer` as a new version of the existing stream whose key document
+/// is `id`. The stream gets a fresh key, which replaces the contents of the key document so its ID and grants are
+/// kept.
+pub fn update_stream(
+    sdk: &BlockingIronOxide,
+    id: &DocumentId,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    let key_bytes = generate_key()?;
+    let key_document = sdk.document_update_bytes(id, key_bytes.to_vec())?;
+    write_stream(&key_bytes, key_document.encrypted_data(), reader, writer)
+}
+
+fn generate_key() -> Result<[u8; KEY_LEN], String> {
+    let mut key_bytes = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key_bytes)
+        .map_err(|_| "Failed to generate a stream encryption key.".to_string())?;
+    Ok(key_bytes)
+}
+
+/// Write the container header holding `key_document`, followed by the segments of `reader` sealed with `key_bytes`.
+fn write_stream(
+    key_bytes: &[u8],
+    key_document: &[u8],
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    let key = aead_key(key_bytes)?;
+    let write_err = |e: io::Error| format!("Couldn't write encrypted file: {e}");
+    writer.write_all(MAGIC).map_err(write_err)?;
+    writer.write_all(&[VERSION]).map_err(write_err)?;
+    write_frame(writer, key_document).map_err(write_err)?;
+    write_segments(&key, reader, writer)
+}
+
+/// Decrypts the segments of a stream container. Created with `StreamDecryptor::new`, which decrypts the key document
+/// up front so nothing needs to be written if the caller doesn't have access.
+pub struct StreamDecryptor<R: Read> {
+    reader: BufReader<R>,
+    key: LessSafeKey,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    /// Read the container header from `reader` and decrypt its key document.
+    pub fn new(sdk: &BlockingIronOxide, mut reader: R) -> Result<Self, String> {
+        let key_document_bytes = read_key_document(&mut reader)
+            .map_err(|e| format!("Failed to read stream header: {e}"))?;
+        let key_document = sdk
+            .document_decrypt(&key_document_bytes)
+            .map_err(|e| format!("Failed to decrypt encrypted document: {e}"))?;
+        let key = aead_key(key_document.decrypted_data())?;
+        Ok(StreamDecryptor {
+            reader: BufReader::new(reader),
+            key,
+        })
+    }
+
+    /// Decrypt every remaining segment into `writer`, returning the number of plaintext bytes written.
+    pub fn decrypt_to(mut self, writer: &mut dyn Write) -> Result<u64, String> {
+        read_segments(&self.key, &mut self.reader, writer)
+    }
+}
+
+/// Split everything readable from `reader` into sealed segments written to `writer`.
+fn write_segments(
+    key: &LessSafeKey,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(reader);
+    let read_err = |e: io::Error| format!("Couldn't read data to encrypt: {e}");
+    let write_err = |e: io::Error| format!("Couldn't write encrypted file: {e}");
+    let mut counter: u32 = 0;
+    loop {
+        let mut segment = Vec::with_capacity(SEGMENT_SIZE + AES_256_GCM.tag_len());
+        (&mut reader)
+            .take(SEGMENT_SIZE as u64)
+            .read_to_end(&mut segment)
+            .map_err(read_err)?;
+        let last = segment.len() < SEGMENT_SIZE || reader.fill_buf().map_err(read_err)?.is_empty();
+        key.seal_in_place_append_tag(segment_nonce(counter, last), Aad::empty(), &mut segment)
+            .map_err(|_| "Failed to encrypt stream segment.".to_string())?;
+        write_frame(writer, &segment).map_err(write_err)?;
+        if last {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| "Input is too large to encrypt as a stream.".to_string())?;
+    }
+    writer.flush().map_err(write_err)
+}
+
+/// Open the sealed segments in `reader`, writing the plaintext to `writer` and returning how many bytes were written.
+fn read_segments<R: BufRead>(
+    key: &LessSafeKey,
+    reader: &mut R,
+    writer: &mut dyn Write,
+) -> Result<u64, String> {
+    let read_err = |e: io::Error| format!("Failed to read encrypted stream: {e}");
+    let write_err = |e: io::Error| format!("Failed to write decrypted document: {e}");
+    let max_segment = SEGMENT_SIZE + AES_256_GCM.tag_len();
+    let mut written: u64 = 0;
+    let mut counter: u32 = 0;
+    loop {
+        let mut segment = read_frame(reader, max_segment).map_err(read_err)?;
+        let last = reader.fill_buf().map_err(read_err)?.is_empty();
+        let plaintext = key
+            .open_in_place(segment_nonce(counter, last), Aad::empty(), &mut segment)
+            .map_err(|_| {
+                "Failed to decrypt stream segment. The file is corrupted or truncated.".to_string()
+            })?;
+        writer.write_all(plaintext).map_err(write_err)?;
+        written += plaintext.len() as u64;
+        if last {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| "Encrypted stream has too many segments.".to_string())?;
+    }
+    writer.flush().map_err(write_err)?;
+    Ok(written)
+}
+
+/// Read the magic, version and key document from the start of a stream container.
+pub fn read_key_document(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut magic = [0u8; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version)?;
+    if magic != MAGIC || version[0] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a supported ironhide stream",
+        ));
+    }
+    read_frame(reader, MAX_KEY_DOCUMENT_SIZE)
+}
+
+fn aead_key(key_bytes: &[u8]) -> Result<LessSafeKey, String> {
+    UnboundKey::new(&AES_256_GCM, key_bytes)
+        .map(LessSafeKey::new)
+        .map_err(|_| "Stream key document did not contain a valid key.".to_string())
+}
+
+/// Every stream has its own key, so the segment counter alone keeps nonces unique. The final byte distinguishes the
+/// last segment so that truncating the stream at a segment boundary is detected.
+fn segment_nonce(counter: u32, last: bool) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[NONCE_LEN - 5..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn write_frame(writer: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_frame(reader: &mut dyn Read, max_len: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "segment length is larger than allowed",
+        ));
+    }
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let key = aead_key(&[7u8; KEY_LEN])?;
+        let mut sealed = Vec::new();
+        write_segments(&key, &mut &plaintext[..], &mut sealed)?;
+        let mut opened = Vec::new();
+        read_segments(&key, &mut &sealed[..], &mut opened)?;
+        Ok(opened)
+    }
+
+    #[test]
+    fn segments_round_trip() {
+        let sizes = [0, 1, SEGMENT_SIZE - 1, SEGMENT_SIZE, SEGMENT_SIZE * 2 + 5];
+        for size in sizes {
+            let plaintext = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+            assert_eq!(round_trip(&plaintext).unwrap(), plaintext, "size {size}");
+        }
+    }
+
+    #[test]
+    fn truncated_stream_fails() {
+        let key = aead_key(&[7u8; KEY_LEN]).unwrap();
+        let plaintext = vec![1u8; SEGMENT_SIZE * 2];
+        let mut sealed = Vec::new();
+        write_segments(&key, &mut &plaintext[..], &mut sealed).unwrap();
+        // drop the final segment, leaving a stream that ends cleanly on a segment boundary
+        let first_segment_len = 4 + SEGMENT_SIZE + AES_256_GCM.tag_len();
+        let truncated = &sealed[..first_segment_len];
+        assert!(read_segments(&key, &mut &truncated[..], &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Self-contained container for documents encrypted without registering them with the IronCore service.
+//!
+//! Unmanaged encryption returns the encrypted data and the encrypted document keys (EDEKs) separately instead of
+//! storing the EDEKs server-side. An unmanaged file starts with `MAGIC` and a version byte, followed by the
+//! length-prefixed EDEKs and then the encrypted data. Both are needed to decrypt, so they're always kept together.
+//!
+//! Since the service has no record of an unmanaged document, access to it is fixed at encryption time. Grant, revoke
+//! and info can't operate on these files.
+
+use ironoxide::prelude::*;
+use std::io;
+
+/// Leading bytes of every unmanaged container. Managed IronCore documents always start with a version byte of 2 and
+/// stream containers with `IHSTREAM`, so none of them can be confused.
+const MAGIC: &[u8] = b"IHUNMNGD";
+const VERSION: u8 = 1;
+const LEN_SIZE: usize = 4;
+
+/// Check whether encrypted bytes hold an unmanaged container.
+pub fn is_unmanaged(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Combine the EDEKs and encrypted data from `document_encrypt_unmanaged` into a single container.
+pub fn encode(edeks: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut container = Vec::with_capacity(MAGIC.len() + 1 + LEN_SIZE + edeks.len() + data.len());
+    container.extend_from_slice(MAGIC);
+    container.push(VERSION);
+    container.extend_from_slice(&(edeks.len() as u32).to_be_bytes());
+    container.extend_from_slice(edeks);
+    container.extend_from_slice(data);
+    container
+}
+
+/// Split a container back into its EDEKs and encrypted data.
+fn decode(container: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let invalid = |message: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid unmanaged file: {message}"),
+        )
+    };
+    let rest = container
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| invalid("missing header"))?;
+    let (version, rest) = rest
+        .split_first()
+        .ok_or_else(|| invalid("missing version"))?;
+    if *version != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    if rest.len() < LEN_SIZE {
+        return Err(invalid("truncated"));
+    }
+    let (len, rest) = rest.split_at(LEN_SIZE);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if rest.len() < len {
+        return Err(invalid("truncated"));
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Decrypt an unmanaged container, returning the plaintext.
+pub fn decrypt(sdk: &BlockingIronOxide, container: &[u8]) -> Result<Vec<u8>, String> {
+    let (edeks, data) =
+        decode(container).map_err(|e| format!("Failed to read encrypted document: {e}"))?;
+    sdk.document_decrypt_unmanaged(data, edeks)
+        .map(|decrypt_result| decrypt_result.decrypted_data().to_vec())
+        .map_err(|e| format!("Failed to decrypt encrypted document: {e}"))
+}
+
+/// Error returned when an operation needs the service's record of a document that was encrypted unmanaged.
+pub fn not_registered() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "it was encrypted with '--unmanaged' so the IronCore service has no record of it",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let bytes = encode(b"edeks", b"encrypted data");
+        assert!(is_unmanaged(&bytes));
+        let (edeks, data) = decode(&bytes).unwrap();
+        assert_eq!(edeks, b"edeks");
+        assert_eq!(data, b"encrypted data");
+    }
+
+    #[test]
+    fn decode_rejects_damaged_containers() {
+        let bytes = encode(b"edeks", b"");
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&bytes[..MAGIC.len() + 3]).is_err());
+        let mut wrong_version = bytes.clone();
+        wrong_version[MAGIC.len()] = VERSION + 1;
+        assert!(decode(&wrong_version).is_err());
+        assert!(!is_unmanaged(&[2, 0, 5]));
+    }
+}
@@ -0,0 +1,155 @@
+use super::{
+    armor,
+    compress::{self, Codec},
+    decrypt::{self, Decrypted},
+    envelope::Envelope,
+    output::{OutputFile, Overwrite},
+    stream,
+};
+use crate::util;
+use clap::Parser;
+use ironoxide::prelude::{BlockingIronOxide, DocumentId};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Cursor, Read},
+    path::{Path, PathBuf},
+};
+use yansi::Paint;
+
+const EXAMPLES: &str = "EXAMPLES
+
+    Replace the contents of 'config.json.iron' with the new 'config.json', keeping everyone who had access.
+        $ ironhide file update config.json.iron config.json
+
+    Replace the contents of 'token.iron' with data from stdin.
+        $ echo \"new secret\" | ironhide file update token.iron -
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Replace the contents of an encrypted file while keeping its document ID and everyone it has been shared with. The
+/// encrypted file is replaced in place, and is never left partially written. Metadata stored with '--preserve' is kept
+/// under its original name and updated to describe the new contents.
+pub struct Update {
+    /// Path of the encrypted file to update.
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    file: PathBuf,
+    /// Path of the file holding the new unencrypted contents. Use '-' to read the new contents from stdin.
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    source: PathBuf,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+}
+
+impl util::GetKeyfile for Update {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+pub fn update_file(
+    sdk: &BlockingIronOxide,
+    Update { file, source, .. }: Update,
+) -> Result<(), String> {
+    let document_id = match util::get_document_id(sdk, &file) {
+        Ok(document_id) => document_id,
+        Err(err) => {
+            util::println_paint(err);
+            return Ok(());
+        }
+    };
+    let from_stdin = source == PathBuf::from("-");
+    let mut input: Box<dyn Read> = if from_stdin {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(&source).map_err(|e| {
+            format!(
+                "Provided path '{}' doesn't exist or is not readable: {e}",
+                source.display()
+            )
+        })?)
+    };
+    // a metadata envelope is kept under its original name, describing the new contents, and so is compression
+    let Decrypted { envelope, codec } = decrypt::decrypt_to(sdk, &file, &mut io::sink())?;
+    if let Some(envelope) = envelope {
+        let header = if from_stdin {
+            let mut contents = Vec::new();
+            input
+                .read_to_end(&mut contents)
+                .map_err(|e| format!("Couldn't read data to encrypt: {e}"))?;
+            let header = envelope.for_new_contents(&contents).to_header();
+            input = Box::new(Cursor::new(contents));
+            header
+        } else {
+            let metadata = Envelope::for_file(&source)
+                .map_err(|e| format!("Couldn't read metadata for '{}': {e}", source.display()))?;
+            Envelope {
+                name: envelope.name,
+                ..metadata
+            }
+            .to_header()
+        };
+        input = Box::new(Cursor::new(header).chain(input));
+    }
+    update_encrypted_file(sdk, &document_id, &file, codec, &mut input)?;
+    util::println_paint(Paint::green(format!(
+        "Encrypted file '{}' successfully updated.",
+        file.display()
+    )));
+    Ok(())
+}
+
+/// Encrypt `input` as the new contents of `document_id`, replacing `path` in the same format it was written in. The
+/// contents are compressed with `codec`, which should be the one the existing contents were compressed with.
+pub fn update_encrypted_file(
+    sdk: &BlockingIronOxide,
+    document_id: &DocumentId,
+    path: &Path,
+    codec: Option<Codec>,
+    input: &mut dyn Read,
+) -> Result<(), String> {
+    let mut input: Box<dyn Read + '_> = match codec {
+        Some(codec) => compress::compress(codec, input)
+            .map_err(|e| format!("Couldn't compress data to encrypt: {e}"))?,
+        None => Box::new(input),
+    };
+    let (armored, is_stream) = existing_format(path).map_err(|e| {
+        format!(
+            "Failed to read bytes from the encrypted document at {}: {e}",
+            path.display()
+        )
+    })?;
+    if is_stream {
+        let mut output = BufWriter::new(OutputFile::create(path, Overwrite::Force)?);
+        armor::write_output(&mut output, armored, |writer| {
+            stream::update_stream(sdk, document_id, &mut input, writer)
+        })?;
+        output
+            .into_inner()
+            .map_err(|e| format!("Couldn't write encrypted file: {}", e.error()))?
+            .finish()
+    } else {
+        let mut contents = Vec::new();
+        input
+            .read_to_end(&mut contents)
+            .map_err(|e| format!("Couldn't read data to encrypt: {e}"))?;
+        let encrypt_result = sdk.document_update_bytes(document_id, contents)?;
+        let mut output = OutputFile::create(path, Overwrite::Force)?;
+        armor::write_output(&mut output, armored, |writer| {
+            writer
+                .write_all(encrypt_result.encrypted_data())
+                .map_err(|e| format!("Couldn't write encrypted file: {e}"))
+        })?;
+        output.finish()
+    }
+}
+
+/// Whether the encrypted file at `path` is armored and whether it's a stream container.
+pub fn existing_format(path: &Path) -> io::Result<(bool, bool)> {
+    let armored = armor::is_armored_file(path)?;
+    let (is_stream, _) = stream::sniff(armor::dearmor(File::open(path)?)?)?;
+    Ok((armored, is_stream))
+}
@@ -0,0 +1,155 @@
+use super::{decrypt, document_id_from_bytes, read_id_bytes};
+use crate::{
+    filter::FilterArgs,
+    jobs::{self, JobsArgs},
+    util,
+};
+use clap::Parser;
+use ironoxide::{IronOxideErr, prelude::*};
+use prettytable::Row;
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const EXAMPLES: &str = "EXAMPLES
+
+    Check that you can still decrypt every encrypted file in the current directory.
+        $ ironhide file verify *.iron
+
+";
+
+#[derive(Parser)]
+#[clap(after_help = EXAMPLES)]
+/// Check that encrypted files can be decrypted, without writing out any of their contents. Each file is fully
+/// decrypted in memory and the plaintext is thrown away. Exits with an error if any of the files can't be decrypted.
+pub struct Verify {
+    /// Path of file(s) to verify.
+    #[clap(value_parser = clap::value_parser!(PathBuf), num_args = 1.., required = true)]
+    files: Vec<PathBuf>,
+    /// Path to location of file which contains keys to use for this operation. Overrides using default key file from
+    /// '~/.iron' directory.
+    #[clap(value_parser = clap::value_parser!(PathBuf), short, long)]
+    keyfile: Option<PathBuf>,
+    #[clap(flatten)]
+    filter: FilterArgs,
+    #[clap(flatten)]
+    jobs: JobsArgs,
+}
+
+impl util::GetKeyfile for Verify {
+    fn get_keyfile(&self) -> Option<&PathBuf> {
+        self.keyfile.as_ref()
+    }
+}
+
+/// Why a file failed to verify, along with the underlying error.
+enum Failure {
+    Unreadable(String),
+    NotEncrypted(String),
+    NoAccess(String),
+    Corrupt(String),
+    /// Unmanaged files have no service record to check access against, so a failed decrypt could be either.
+    NoAccessOrCorrupt(String),
+}
+
+impl Failure {
+    fn status(&self) -> &'static str {
+        match self {
+            Failure::Unreadable(_) => "unreadable",
+            Failure::NotEncrypted(_) => "not an ironhide file",
+            Failure::NoAccess(_) => "no access",
+            Failure::Corrupt(_) => "corrupt",
+            Failure::NoAccessOrCorrupt(_) => "no access or corrupt",
+        }
+    }
+
+    fn details(&self) -> &str {
+        match self {
+            Failure::Unreadable(e)
+            | Failure::NotEncrypted(e)
+            | Failure::NoAccess(e)
+            | Failure::Corrupt(e)
+            | Failure::NoAccessOrCorrupt(e) => e,
+        }
+    }
+}
+
+pub fn verify_files(
+    sdk: &BlockingIronOxide,
+    Verify {
+        files,
+        filter,
+        jobs,
+        ..
+    }: Verify,
+) -> Result<(), String> {
+    let files = util::collect_files(&files, false, &filter, |_| true)?;
+    let results = jobs::run(&files, jobs.count(), "verified", |path| {
+        verify_file(sdk, path)
+    });
+    let mut table = table!([Fbb=>"File", "Status", "Details"]);
+    let mut failures = 0;
+    for (path, result) in files.iter().zip(&results) {
+        let row = match result {
+            Ok(()) => vec![cell!(Fw -> path.display()), cell!(Fg -> "OK"), cell!("")],
+            Err(failure) => {
+                failures += 1;
+                vec![
+                    cell!(Fw -> path.display()),
+                    cell!(Fr -> failure.status()),
+                    cell!(Fw -> failure.details()),
+                ]
+            }
+        };
+        table.add_row(Row::new(row));
+    }
+    table.printstd();
+
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "{failures} of {} file(s) couldn't be decrypted.",
+            files.len()
+        ))
+    }
+}
+
+/// Check the header of the file at `path`, whether the caller has access to its document, and finally that the whole
+/// file decrypts.
+fn verify_file(sdk: &BlockingIronOxide, path: &Path) -> Result<(), Failure> {
+    let unmanaged = match read_id_bytes(path) {
+        Ok(id_bytes) => {
+            let document_id = document_id_from_bytes(sdk, &id_bytes).map_err(|e| match e {
+                IronOxideErr::DocumentHeaderParseFailure(_) => Failure::NotEncrypted(
+                    "File doesn't appear to be an encrypted file.".to_string(),
+                ),
+                e => Failure::Corrupt(e.to_string()),
+            })?;
+            // only users and groups with access to the document can see its metadata
+            sdk.document_get_metadata(&document_id)
+                .map_err(|e| Failure::NoAccess(e.to_string()))?;
+            false
+        }
+        Err(e) if e.kind() == ErrorKind::Unsupported => true,
+        Err(e) => return Err(read_failure(e)),
+    };
+    decrypt::decrypt_to(sdk, path, &mut io::sink())
+        .map(|_| ())
+        .map_err(|e| {
+            if unmanaged {
+                Failure::NoAccessOrCorrupt(e)
+            } else {
+                Failure::Corrupt(e)
+            }
+        })
+}
+
+/// Armored and stream files that can't be decoded were recognized as encrypted files, so they're corrupt.
+fn read_failure(e: io::Error) -> Failure {
+    match e.kind() {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Failure::Corrupt(e.to_string()),
+        _ => Failure::Unreadable(e.to_string()),
+    }
+}
@@ -0,0 +1,221 @@
+//! Choosing which files batch operations work on.
+//!
+//! '--include' and '--exclude' take glob patterns, which are matched against a file's name and against its path
+//! relative to the directory it was found in with '-r', or the path it was given as. A '.ironhideignore' file written
+//! in gitignore syntax applies to the directory it's in and everything beneath it when walking directories with '-r',
+//! including ignore files in the parent directories of a walked directory. Files named explicitly are always worked on
+//! if they pass '--include' and '--exclude'. Ignored and excluded directories aren't walked at all.
+
+use clap::Args;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const IGNORE_FILE_NAME: &str = ".ironhideignore";
+
+/// Flags choosing which of the provided files to work on.
+#[derive(Args, Default)]
+pub struct FilterArgs {
+    /// Only work on files matching the given glob pattern, e.g. '*.json'. Patterns are matched against file names and
+    /// against paths relative to the directories given with '-r'. Can be provided more than once.
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Skip files and directories matching the given glob pattern, e.g. 'target' or '*.log'. Can be provided more than
+    /// once. With '-r', files listed in a '.ironhideignore' file, which uses gitignore syntax, in their directory or
+    /// any directory above it are skipped as well.
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+}
+
+impl FilterArgs {
+    pub fn build(&self) -> Result<Filter, String> {
+        Ok(Filter {
+            include: (!self.include.is_empty())
+                .then(|| glob_set(&self.include))
+                .transpose()?,
+            exclude: glob_set(&self.exclude)?,
+        })
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Invalid glob pattern '{pattern}': {e}"))?,
+        );
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Compiled '--include' and '--exclude' patterns.
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// Whether to work on the file at `relative`, its path within the directory being walked or as it was given.
+    pub fn includes_file(&self, relative: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| matches(include, relative))
+            && !matches(&self.exclude, relative)
+    }
+
+    /// Whether to walk the directory at `relative`, its path within the directory being walked.
+    pub fn includes_dir(&self, relative: &Path) -> bool {
+        !matches(&self.exclude, relative)
+    }
+}
+
+fn matches(globs: &GlobSet, relative: &Path) -> bool {
+    globs.is_match(relative)
+        || relative
+            .file_name()
+            .is_some_and(|name| globs.is_match(name))
+}
+
+/// The '.ironhideignore' files that apply to the directory being walked, from the outermost in.
+#[derive(Default)]
+pub struct IgnoreFiles(Vec<Gitignore>);
+
+impl IgnoreFiles {
+    /// The ignore files in every directory above `path`, which apply to it and to anything beneath it.
+    pub fn for_ancestors(path: &Path) -> Result<Self, String> {
+        let mut ignores = IgnoreFiles::default();
+        if let Some(parent) = absolute(path).parent() {
+            for dir in parent.ancestors().collect::<Vec<_>>().into_iter().rev() {
+                ignores.enter(dir)?;
+            }
+        }
+        Ok(ignores)
+    }
+
+    /// Start applying the ignore file in `dir`, if there is one. Returns whether there was, in which case `leave` should
+    /// be called once the walk is done with `dir`.
+    pub fn enter(&mut self, dir: &Path) -> Result<bool, String> {
+        let path = dir.join(IGNORE_FILE_NAME);
+        if !path.is_file() {
+            return Ok(false);
+        }
+        let invalid = |e: ignore::Error| format!("Invalid ignore file '{}': {e}", path.display());
+        // patterns are matched against absolute paths, so the same file applies however a path was given
+        let mut builder = GitignoreBuilder::new(absolute_dir(dir));
+        if let Some(e) = builder.add(&path) {
+            return Err(invalid(e));
+        }
+        self.0.push(builder.build().map_err(invalid)?);
+        Ok(true)
+    }
+
+    pub fn leave(&mut self) {
+        self.0.pop();
+    }
+
+    /// Whether `path` is ignored, either itself or because a directory it's in is. The innermost ignore file with a
+    /// matching pattern decides, so a nested file can re-include what an outer one ignored with '!'.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = absolute(path);
+        self.0
+            .iter()
+            .rev()
+            .filter(|ignores| path.starts_with(ignores.path()))
+            .map(|ignores| ignores.matched_path_or_any_parents(&path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
+
+/// `dir` as an absolute path with symlinks resolved, or as it is if that fails.
+fn absolute_dir(dir: &Path) -> PathBuf {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// `path` with its directory made absolute. The file name itself is kept, so a symlink isn't replaced by its target.
+fn absolute(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => absolute_dir(parent).join(name),
+        _ => absolute_dir(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> Filter {
+        FilterArgs {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn include_and_exclude_patterns() {
+        let everything = filter(&[], &[]);
+        assert!(everything.includes_file(Path::new("a/b.txt")));
+
+        let json = filter(&["*.json"], &["package*.json"]);
+        assert!(json.includes_file(Path::new("config/app.json")));
+        assert!(!json.includes_file(Path::new("config/package-lock.json")));
+        assert!(!json.includes_file(Path::new("README.md")));
+        // directories aren't pruned by '--include', only the files in them
+        assert!(json.includes_dir(Path::new("config")));
+
+        let build = filter(&[], &["target", "logs/**"]);
+        assert!(!build.includes_dir(Path::new("crate/target")));
+        assert!(!build.includes_file(Path::new("logs/2024/app.log")));
+        assert!(build.includes_file(Path::new("src/logs.rs")));
+
+        assert!(
+            FilterArgs {
+                include: vec!["[".to_string()],
+                exclude: vec![],
+            }
+            .build()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn nested_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        std::fs::create_dir(&nested).unwrap();
+        std::fs::write(dir.path().join(IGNORE_FILE_NAME), "*.log\nbuild/\n").unwrap();
+        std::fs::write(nested.join(IGNORE_FILE_NAME), "!keep.log\n").unwrap();
+
+        let mut ignores = IgnoreFiles::default();
+        assert!(ignores.enter(dir.path()).unwrap());
+        assert!(ignores.is_ignored(&dir.path().join("app.log"), false));
+        assert!(ignores.is_ignored(&dir.path().join("build"), true));
+        assert!(!ignores.is_ignored(&dir.path().join("build"), false));
+        assert!(!ignores.is_ignored(&dir.path().join("app.txt"), false));
+        assert!(ignores.enter(&nested).unwrap());
+        assert!(!ignores.is_ignored(&nested.join("keep.log"), false));
+        assert!(ignores.is_ignored(&nested.join("other.log"), false));
+        ignores.leave();
+        assert!(!ignores.enter(&nested.join("missing")).unwrap());
+
+        // ignore files above a path apply to it, including to what's inside ignored directories
+        let ancestors = IgnoreFiles::for_ancestors(&nested.join("keep.log")).unwrap();
+        assert!(!ancestors.is_ignored(&nested.join("keep.log"), false));
+        assert!(ancestors.is_ignored(&nested.join("other.log"), false));
+        let ancestors = IgnoreFiles::for_ancestors(&dir.path().join("build/out.txt")).unwrap();
+        assert!(ancestors.is_ignored(&dir.path().join("build/out.txt"), false));
+    }
+}
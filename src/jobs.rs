@@ -0,0 +1,181 @@
+//! Running per-file work on a pool of worker threads, with progress reporting for batches of files.
+//!
+//! Every worker shares the same `BlockingIronOxide`, which is safe to call from multiple threads. Progress goes to
+//! stderr so it never mixes with output written to stdout: a progress bar when stderr is a terminal, and one line per
+//! completed file otherwise, e.g. when running in CI.
+
+use clap::Args;
+use std::{
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+const BAR_WIDTH: usize = 30;
+
+/// Whether a progress bar is currently drawn on the terminal, so other output knows to clear it first.
+static BAR_VISIBLE: AtomicBool = AtomicBool::new(false);
+/// Held while writing to the terminal, so the progress bar and other output don't interleave.
+static TERMINAL: Mutex<()> = Mutex::new(());
+
+/// Flag controlling how many files are worked on at once.
+#[derive(Args)]
+pub struct JobsArgs {
+    /// Number of files to work on at the same time.
+    #[clap(short, long, default_value = "1")]
+    jobs: NonZeroUsize,
+}
+
+impl JobsArgs {
+    pub fn count(&self) -> usize {
+        self.jobs.get()
+    }
+}
+
+/// Run `action` on every file using up to `jobs` worker threads, returning the results in the same order as `files`.
+/// Progress is reported as each file completes, using `action_verb` to describe a successful file.
+pub fn run<T, E, F>(
+    files: &[PathBuf],
+    jobs: usize,
+    action_verb: &str,
+    action: F,
+) -> Vec<Result<T, E>>
+where
+    T: Send,
+    E: Send,
+    F: Fn(&PathBuf) -> Result<T, E> + Sync,
+{
+    let progress = Progress::new(files.len(), action_verb);
+    let workers = jobs.clamp(1, files.len().max(1));
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<T, E>)> = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut completed = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            break;
+                        };
+                        let result = action(file);
+                        progress.completed(file, result.is_ok());
+                        completed.push((index, result));
+                    }
+                    completed
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            // a panicking action should still take the whole process down
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    });
+    progress.finish();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Clear the progress bar, if one is showing, so a message can be printed on its own line. The bar is redrawn when
+/// the next file completes.
+pub fn clear_progress_bar() {
+    if BAR_VISIBLE.load(Ordering::Relaxed) {
+        let _guard = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+        clear_line();
+    }
+}
+
+struct Progress<'a> {
+    total: usize,
+    completed: AtomicUsize,
+    action_verb: &'a str,
+    /// Whether to draw a bar rather than printing a line per file.
+    bar: bool,
+}
+
+impl<'a> Progress<'a> {
+    fn new(total: usize, action_verb: &'a str) -> Self {
+        Progress {
+            total,
+            completed: AtomicUsize::new(0),
+            action_verb,
+            bar: atty::is(atty::Stream::Stderr),
+        }
+    }
+
+    /// Report on a completed file. Nothing is reported for a single file, which has its own messages.
+    fn completed(&self, file: &Path, succeeded: bool) {
+        if self.total < 2 {
+            return;
+        }
+        let _guard = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.bar {
+            let filled = BAR_WIDTH * completed / self.total;
+            eprint!(
+                "\r\x1b[2K[{}{}] {completed}/{} {}",
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                self.total,
+                self.action_verb
+            );
+            let _ = io::stderr().flush();
+            BAR_VISIBLE.store(true, Ordering::Relaxed);
+        } else {
+            let outcome = if succeeded {
+                self.action_verb
+            } else {
+                "failed"
+            };
+            eprintln!(
+                "[{completed}/{}] {outcome} '{}'",
+                self.total,
+                file.display()
+            );
+        }
+    }
+
+    fn finish(&self) {
+        if BAR_VISIBLE.load(Ordering::Relaxed) {
+            let _guard = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+            clear_line();
+        }
+    }
+}
+
+fn clear_line() {
+    eprint!("\r\x1b[2K");
+    let _ = io::stderr().flush();
+    BAR_VISIBLE.store(false, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_keep_file_order() {
+        let files = (0..20)
+            .map(|i| PathBuf::from(i.to_string()))
+            .collect::<Vec<_>>();
+        let results = run(&files, 4, "checked", |file| {
+            let i: usize = file.to_str().unwrap().parse().unwrap();
+            if i % 3 == 0 { Err(i) } else { Ok(i) }
+        });
+        assert_eq!(results.len(), files.len());
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result, if i % 3 == 0 { Err(i) } else { Ok(i) });
+        }
+        assert!(run(&[], 4, "checked", |_| Ok::<(), ()>(())).is_empty());
+    }
+}
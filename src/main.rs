@@ -12,9 +12,12 @@ use yansi::Paint;
 extern crate prettytable;
 
 mod auth;
+mod exec;
 mod file;
+mod filter;
 mod group;
 mod group_maps;
+mod jobs;
 mod logout;
 mod user;
 mod util;
@@ -29,6 +32,8 @@ struct Ironhide {
 
 #[derive(Parser)]
 enum IronhideSubcommands {
+    #[clap(name = "exec")]
+    Exec(exec::Exec),
     #[clap(name = "file")]
     File(file::File),
     #[clap(name = "group")]
@@ -49,15 +54,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let ironhide = Ironhide::parse();
 
     match ironhide.subcmd {
+        IronhideSubcommands::Exec(exec) => {
+            let sdk = util::initialize_sdk(exec.get_keyfile())?;
+            // exit with the command's exit code rather than ironhide's own
+            let code = exec::exec(&sdk, exec)?;
+            std::process::exit(code)
+        }
         IronhideSubcommands::File(file) => {
             // Every file subcommand needs the SDK so we can try to initialize here.
             let sdk = util::initialize_sdk(file.get_keyfile())?;
             match file.subcmd {
                 FileSubcommands::Decrypt(decrypt) => file::decrypt::decrypt_files(&sdk, decrypt),
-                FileSubcommands::Encrypt(encrypt) => file::encrypt::encrypt_files(&sdk, encrypt),
+                FileSubcommands::Edit(edit) => file::edit::edit_file(&sdk, edit),
+                FileSubcommands::Encrypt(encrypt) => file::encrypt::encrypt_files(&sdk, *encrypt),
                 FileSubcommands::Info(info) => file::info::investigate_files(&sdk, info),
                 FileSubcommands::Grant(grant) => file::grant::grant_files(&sdk, grant),
+                FileSubcommands::List(list) => file::list::list_files(&sdk, list),
+                FileSubcommands::Rekey(rekey) => file::rekey::rekey_file(&sdk, rekey),
+                FileSubcommands::Rename(rename) => file::rename::rename_file(&sdk, rename),
                 FileSubcommands::Revoke(revoke) => file::revoke::revoke_files(&sdk, revoke),
+                FileSubcommands::Update(update) => file::update::update_file(&sdk, update),
+                FileSubcommands::Verify(verify) => file::verify::verify_files(&sdk, verify),
             }
         }
         IronhideSubcommands::Group(group) => {
@@ -1,4 +1,7 @@
+use crate::file;
+use crate::filter::{Filter, FilterArgs, IGNORE_FILE_NAME, IgnoreFiles};
 use crate::group_maps::{convert_group_names_to_ids, get_group_maps};
+use crate::jobs;
 use crate::{IronhideErr, group_maps};
 use fancy_regex::Regex;
 use ironoxide::prelude::*;
@@ -158,6 +161,7 @@ pub fn print_paint(paint: yansi::Paint<String>) {
 pub fn println_paint(paint: yansi::Paint<String>) {
     // only print to stdout if we're not being piped somewhere
     if atty::is(atty::Stream::Stdout) {
+        jobs::clear_progress_bar();
         println!("{}", paint);
     }
 }
@@ -269,16 +273,466 @@ pub fn is_group_id(group_identifier: &str) -> bool {
     group_identifier.starts_with(GROUP_ID_PREFIX)
 }
 
-// Run an action closure across all files and print messages for the successes and failures.
-pub fn act_on_all_files<F, T: Clone>(
+/// Get the ID of the document in the encrypted file at `path`. Errors are formatted for display to the user.
+pub fn get_document_id(sdk: &BlockingIronOxide, path: &Path) -> Result<DocumentId, Paint<String>> {
+    let file_name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let encrypted_document = file::read_id_bytes(path)
+        .map_err(|e| Paint::red(format!("Failed to read '{}': {}.", file_name, e)))?;
+    file::document_id_from_bytes(sdk, &encrypted_document).map_err(|e| match e {
+        ironoxide::IronOxideErr::DocumentHeaderParseFailure(_) => Paint::red(format!(
+            "Failed to parse '{}'. File doesn't appear to be an encrypted file.",
+            file_name
+        )),
+        _ => Paint::red(format!(
+            "Failed to get the document id for {}: {}.",
+            file_name, e
+        )),
+    })
+}
+
+/// Add the users and groups who can decrypt the encrypted file at `path` to `users` and `groups`, skipping any that
+/// are already there. Used by '--same-access-as'.
+pub fn add_access_from(
+    sdk: &BlockingIronOxide,
+    path: &Path,
+    users: &mut Vec<UserId>,
+    groups: &mut Vec<Either<GroupName, GroupId>>,
+) -> Result<(), String> {
+    let document_id = get_document_id(sdk, path).map_err(|e| e.inner().clone())?;
+    let metadata = sdk.document_get_metadata(&document_id).map_err(|e| {
+        format!(
+            "Failed to get the users and groups with access to '{}': {e}",
+            path.display()
+        )
+    })?;
+    for user in metadata.visible_to_users() {
+        if !users.contains(user.id()) {
+            users.push(user.id().clone());
+        }
+    }
+    for group in metadata.visible_to_groups() {
+        let already_present = groups.iter().any(|existing| match existing {
+            Either::Left(name) => group.name() == Some(name),
+            Either::Right(id) => group.id() == id,
+        });
+        if !already_present {
+            groups.push(Either::Right(group.id().clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Expand the paths given on the command line into the files to operate on. When `recursive` is set, directories are
+/// walked and every file beneath them that `include` and the `filter` accept, and that isn't listed in a
+/// '.ironhideignore' file in its directory or a directory above it, is returned in a stable order. Files given
+/// explicitly only have to pass the `filter`. Prints a note about how many files were skipped.
+pub fn collect_files(
+    paths: &[PathBuf],
+    recursive: bool,
+    filter: &FilterArgs,
+    include: impl Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>, String> {
+    let filter = filter.build()?;
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    let mut skipped_explicit = 0;
+    for path in paths {
+        if recursive && path.is_dir() {
+            let mut walk = Walk {
+                root: path,
+                filter: &filter,
+                include: &include,
+                ignores: IgnoreFiles::for_ancestors(path)?,
+                files: &mut files,
+                skipped: &mut skipped,
+            };
+            walk.dir(path)?;
+        } else if filter.includes_file(path) {
+            files.push(path.clone());
+        } else {
+            skipped_explicit += 1;
+        }
+    }
+    let file_or_files = |count: usize| if count == 1 { "file" } else { "files" };
+    if skipped > 0 {
+        println_paint(Paint::yellow(format!(
+            "Skipped {skipped} {} found in the provided directories.",
+            file_or_files(skipped)
+        )));
+    }
+    if skipped_explicit > 0 {
+        println_paint(Paint::yellow(format!(
+            "Skipped {skipped_explicit} provided {} that didn't match '--include' or matched '--exclude'.",
+            file_or_files(skipped_explicit)
+        )));
+    }
+    Ok(files)
+}
+
+/// State for walking one of the directories given to `collect_files`.
+struct Walk<'a, F: Fn(&Path) -> bool> {
+    root: &'a Path,
+    filter: &'a Filter,
+    include: &'a F,
+    ignores: IgnoreFiles,
+    files: &'a mut Vec<PathBuf>,
+    skipped: &'a mut usize,
+}
+
+impl<F: Fn(&Path) -> bool> Walk<'_, F> {
+    fn dir(&mut self, dir: &Path) -> Result<(), String> {
+        let read_err =
+            |e: std::io::Error| format!("Couldn't read directory '{}': {e}", dir.display());
+        let mut entries = fs::read_dir(dir)
+            .map_err(read_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_err)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        let has_ignore_file = self.ignores.enter(dir)?;
+        for entry in entries {
+            let path = entry.path();
+            let relative = path.strip_prefix(self.root).unwrap_or(&path);
+            // symlinked directories aren't followed so that a link cycle can't send us around forever
+            if entry.file_type().map_err(read_err)?.is_dir() {
+                // skipped directories aren't counted, as they're usually things like '.git' that nobody expects to
+                // be included
+                if self.filter.includes_dir(relative) && !self.ignores.is_ignored(&path, true) {
+                    self.dir(&path)?;
+                }
+            } else if entry.file_name() == IGNORE_FILE_NAME {
+                continue;
+            } else if path.is_file()
+                && (self.include)(&path)
+                && self.filter.includes_file(relative)
+                && !self.ignores.is_ignored(&path, false)
+            {
+                self.files.push(path);
+            } else {
+                *self.skipped += 1;
+            }
+        }
+        if has_ignore_file {
+            self.ignores.leave();
+        }
+        Ok(())
+    }
+}
+
+/// Build the output path for `file` inside `out_dir`, mirroring its location relative to the directory in `inputs` it
+/// was found under. Files that were given directly are placed at the top of `out_dir`. Any missing parent directories
+/// are created.
+pub fn mirror_output_path(
+    out_dir: &Path,
+    file: &Path,
+    inputs: &[PathBuf],
+) -> Result<PathBuf, String> {
+    let relative = inputs
+        .iter()
+        .filter(|input| input.is_dir())
+        .find_map(|input| file.strip_prefix(input).ok())
+        .map(Path::to_path_buf)
+        .or_else(|| file.file_name().map(PathBuf::from))
+        .ok_or_else(|| format!("Invalid input file '{}'", file.display()))?;
+    let output = out_dir.join(relative);
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            format!(
+                "Couldn't create output directory '{}': {e}",
+                parent.display()
+            )
+        })?;
+    }
+    Ok(output)
+}
+
+/// Make sure `dir` is a directory that multiple outputs can be written into, creating it if `create` is set.
+pub fn prepare_output_dir(dir: &Path, create: bool) -> Result<(), String> {
+    if dir.is_dir() {
+        Ok(())
+    } else if dir.exists() {
+        Err(format!(
+            "Output path '{}' isn't a directory. Provide a directory when writing multiple files.",
+            dir.display()
+        ))
+    } else if create {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Couldn't create output directory '{}': {e}", dir.display()))
+    } else {
+        Err(format!(
+            "Output directory '{}' doesn't exist. Use '--mkdir' to create it.",
+            dir.display()
+        ))
+    }
+}
+
+/// Check that no two `inputs` would be written to the same path in `outputs`, which holds the output path for each
+/// input in the same order, and that no input would be written over itself. Every collision is reported before
+/// anything is written.
+pub fn check_output_collisions(inputs: &[PathBuf], outputs: &[PathBuf]) -> Result<(), String> {
+    let overwritten_inputs = inputs
+        .iter()
+        .zip(outputs)
+        .filter(|(input, output)| input == output)
+        .map(|(input, _)| format!("'{}' would be written over itself", input.display()));
+    let collisions = outputs
+        .iter()
+        .zip(inputs)
+        .into_group_map()
+        .into_iter()
+        .filter(|(_, inputs)| inputs.len() > 1)
+        .map(|(output, inputs)| {
+            format!(
+                "'{}' would be written from {}",
+                output.display(),
+                inputs
+                    .iter()
+                    .map(|input| format!("'{}'", input.display()))
+                    .join(", ")
+            )
+        })
+        .sorted()
+        .chain(overwritten_inputs)
+        .collect_vec();
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Some files would be written to a path that is already taken:\n{}",
+            collisions.join("\n")
+        ))
+    }
+}
+
+/// Writer that computes the SHA-256 digest of everything written to it.
+pub struct Sha256Writer(ring::digest::Context);
+
+impl Sha256Writer {
+    pub fn new() -> Self {
+        Sha256Writer(ring::digest::Context::new(&ring::digest::SHA256))
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+}
+
+impl std::io::Write for Sha256Writer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader that computes the SHA-256 digest and length of everything read through it.
+pub struct Sha256Reader<R> {
+    inner: R,
+    hasher: Sha256Writer,
+    len: u64,
+}
+
+impl<R> Sha256Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Sha256Reader {
+            inner,
+            hasher: Sha256Writer::new(),
+            len: 0,
+        }
+    }
+
+    /// Number of bytes read and their SHA-256 digest.
+    pub fn finish(self) -> (u64, Vec<u8>) {
+        (self.len, self.hasher.finish())
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for Sha256Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.0.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
+/// Lowercase hex encoding of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Compute the SHA-256 digest of the file at `path` without reading it all into memory.
+pub fn sha256_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut hasher = Sha256Writer::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Overwrite the contents of the file at `path` with zeros and sync it to disk. On copy-on-write filesystems and SSDs
+/// the old blocks may survive, so this is a best effort at making the original contents unrecoverable.
+pub fn overwrite_with_zeros(path: &Path) -> std::io::Result<()> {
+    use std::io::Read;
+    let len = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    std::io::copy(&mut std::io::repeat(0).take(len), &mut file)?;
+    file.sync_all()
+}
+
+/// Signals sent to this process that are passed on to a child started with `run_in_foreground`.
+#[cfg(unix)]
+const FORWARDED_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2];
+
+/// Process ID of the child started with `run_in_foreground`, or 0 before it has started.
+#[cfg(unix)]
+static FOREGROUND_PID: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+/// Last of `FORWARDED_SIGNALS` received while running a child, or 0 if there wasn't one.
+#[cfg(unix)]
+static FORWARDED_SIGNAL: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    use std::sync::atomic::Ordering;
+    // recorded before checking for the child, so a signal that arrives before it has started isn't lost
+    FORWARDED_SIGNAL.store(signal, Ordering::SeqCst);
+    let pid = FOREGROUND_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        // SAFETY: kill is async-signal-safe
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// How a child started with `run_in_foreground` finished.
+pub struct ForegroundExit {
+    pub status: std::process::ExitStatus,
+    /// Whether this process was asked to terminate with SIGTERM or SIGHUP while the child ran. The signal was passed on
+    /// to the child.
+    pub terminated: bool,
+}
+
+/// Run `command` as a child that owns the terminal and wait for it to exit. Ctrl-C and Ctrl-\ are left to the child,
+/// which gets them from the terminal directly, while SIGTERM, SIGHUP, SIGUSR1 and SIGUSR2 sent to this process are
+/// passed on to it. Either way we're still around to clean up after the child exits.
+pub fn run_in_foreground(command: &mut std::process::Command) -> std::io::Result<ForegroundExit> {
+    let handlers = ForegroundSignals::install();
+    handlers.restore_in_child(command);
+    let mut child = command.spawn()?;
+    handlers.forward_to(&child);
+    let status = child.wait()?;
+    Ok(ForegroundExit {
+        status,
+        terminated: handlers.terminated(),
+    })
+}
+
+/// Signal handlers in place while a child runs in the foreground. The previous handlers are restored on drop.
+struct ForegroundSignals {
+    #[cfg(unix)]
+    previous: Vec<(libc::c_int, libc::sighandler_t)>,
+}
+
+impl ForegroundSignals {
+    fn install() -> Self {
+        #[cfg(unix)]
+        {
+            let forward = forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            let previous = [
+                (libc::SIGINT, libc::SIG_IGN),
+                (libc::SIGQUIT, libc::SIG_IGN),
+            ]
+            .into_iter()
+            .chain(FORWARDED_SIGNALS.map(|signal| (signal, forward)))
+            // SAFETY: the handlers are either SIG_IGN or only call kill, and the previous ones are restored on drop
+            .map(|(signal, handler)| (signal, unsafe { libc::signal(signal, handler) }))
+            .collect();
+            ForegroundSignals { previous }
+        }
+        #[cfg(not(unix))]
+        ForegroundSignals {}
+    }
+
+    /// Give the child the handlers that were in place before `install`. Ignored signals stay ignored across exec, so
+    /// without this the child couldn't be interrupted.
+    fn restore_in_child(&self, command: &mut std::process::Command) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let previous = self.previous.clone();
+            // SAFETY: only calls signal, which is async-signal-safe, between fork and exec
+            unsafe {
+                command.pre_exec(move || {
+                    for (signal, handler) in &previous {
+                        libc::signal(*signal, *handler);
+                    }
+                    Ok(())
+                })
+            };
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+    }
+
+    fn forward_to(&self, child: &std::process::Child) {
+        #[cfg(unix)]
+        {
+            use std::sync::atomic::Ordering;
+            FOREGROUND_PID.store(child.id() as i32, Ordering::SeqCst);
+            // pass on anything that arrived while the child was starting
+            let signal = FORWARDED_SIGNAL.load(Ordering::SeqCst);
+            if signal != 0 {
+                // SAFETY: the child hasn't been waited on, so its process ID can't have been reused
+                unsafe { libc::kill(child.id() as i32, signal) };
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = child;
+    }
+
+    fn terminated(&self) -> bool {
+        #[cfg(unix)]
+        {
+            let signal = FORWARDED_SIGNAL.load(std::sync::atomic::Ordering::SeqCst);
+            signal == libc::SIGTERM || signal == libc::SIGHUP
+        }
+        #[cfg(not(unix))]
+        false
+    }
+}
+
+impl Drop for ForegroundSignals {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            use std::sync::atomic::Ordering;
+            for (signal, handler) in &self.previous {
+                // SAFETY: restores the handler that was in place before `install`
+                unsafe { libc::signal(*signal, *handler) };
+            }
+            FOREGROUND_PID.store(0, Ordering::SeqCst);
+            FORWARDED_SIGNAL.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+// Run an action closure across all files, `jobs` at a time, and print messages for the successes and failures. The
+// results of the successes are returned in the same order as `files`, even when some failed.
+pub fn act_on_all_files<F, T: Send>(
     files: &[PathBuf],
+    jobs: usize,
     action: F,
     action_verb: &str,
-) -> Result<Option<T>, (String, Option<T>)>
+) -> Result<Vec<T>, (String, Vec<T>)>
 where
-    F: FnMut(&PathBuf) -> Result<T, String>,
+    F: Fn(&PathBuf) -> Result<T, String> + Sync,
 {
-    let (successes, failures): (Vec<_>, Vec<_>) = files.iter().map(action).partition_result();
+    let (successes, failures): (Vec<_>, Vec<_>) = jobs::run(files, jobs, action_verb, action)
+        .into_iter()
+        .partition_result();
     if !successes.is_empty() {
         let file_or_files = match successes.len() {
             1 => "file",
@@ -303,10 +757,10 @@ where
         )));
         Err((
             "Not all file operations were successful.".to_string(),
-            successes.first().cloned(),
+            successes,
         ))
     } else {
-        Ok(successes.first().cloned())
+        Ok(successes)
     }
 }
 
@@ -327,53 +781,43 @@ pub fn execute_permissioning_operation(
     users: &[UserId],
     groups: &[Either<GroupName, GroupId>],
     files: &[PathBuf],
+    jobs: usize,
     sdk: &BlockingIronOxide,
     operation: PermissionOperation,
 ) -> Vec<(String, Option<DocumentAccessResult>)> {
     let (groups_by_name, _) = get_group_maps(sdk);
     let requested_groups = convert_group_names_to_ids(groups, &groups_by_name);
     let application_list = collect_users_and_groups(users, &requested_groups);
-    let results = files
+    let action_verb = match operation {
+        PermissionOperation::Grant => "granted",
+        PermissionOperation::Revoke => "revoked",
+    };
+    let results = jobs::run(files, jobs, action_verb, |infile| {
+        let file_name = infile.display();
+        get_document_id(sdk, infile).and_then(|id| {
+            let f = match operation {
+                PermissionOperation::Grant => BlockingIronOxide::document_grant_access,
+                PermissionOperation::Revoke => BlockingIronOxide::document_revoke_access,
+            };
+            f(sdk, &id, &application_list).map_err(|e| {
+                Paint::red(format!(
+                    "Catastrophically failed to {operation} access to anything for {}: {}",
+                    file_name, e
+                ))
+            })
+        })
+    });
+    files
         .iter()
-        .map(|infile| {
-            let file = fs::read(infile).unwrap();
-            let file_name = infile.file_name().unwrap().to_string_lossy();
-
-            let res = sdk
-                .document_get_id_from_bytes(&file)
-                .map_err(|e| match e {
-                    ironoxide::IronOxideErr::DocumentHeaderParseFailure(_) => Paint::red(format!(
-                        "Failed to parse '{}'. File doesn't appear to be an encrypted file.",
-                        file_name
-                    )),
-                    _ => Paint::red(format!(
-                        "Failed to get the document id for {}: {}.",
-                        file_name, e
-                    )),
-                })
-                .and_then(|id| {
-                    let f = match operation {
-                        PermissionOperation::Grant => BlockingIronOxide::document_grant_access,
-                        PermissionOperation::Revoke => BlockingIronOxide::document_revoke_access,
-                    };
-                    f(sdk, &id, &application_list).map_err(|e| {
-                        Paint::red(format!(
-                            "Catastrophically failed to {operation} access to anything for {}: {}",
-                            file_name, e
-                        ))
-                    })
-                });
-
-            match res {
-                Ok(access_result) => (infile.to_str().unwrap().to_string(), Some(access_result)),
-                Err(e) => {
-                    println_paint(e);
-                    (infile.to_str().unwrap().to_string(), None)
-                }
+        .zip(results)
+        .map(|(infile, res)| match res {
+            Ok(access_result) => (infile.display().to_string(), Some(access_result)),
+            Err(e) => {
+                println_paint(e);
+                (infile.display().to_string(), None)
             }
         })
-        .collect();
-    results
+        .collect()
 }
 
 pub fn build_permissioning_result_table(
@@ -514,4 +958,129 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_collect_files_recursive_and_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("in/nested")).unwrap();
+        fs::write(root.join("in/a.txt"), "a").unwrap();
+        fs::write(root.join("in/b.txt.iron"), "b").unwrap();
+        fs::write(root.join("in/nested/c.txt"), "c").unwrap();
+        fs::write(root.join("single.txt"), "s").unwrap();
+        let inputs = vec![root.join("in"), root.join("single.txt")];
+
+        let no_filter = FilterArgs::default();
+        let files = collect_files(&inputs, true, &no_filter, |path| {
+            path.extension() != Some("iron".as_ref())
+        })
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                root.join("in/a.txt"),
+                root.join("in/nested/c.txt"),
+                root.join("single.txt")
+            ]
+        );
+
+        let out_dir = root.join("out");
+        assert_eq!(
+            mirror_output_path(&out_dir, &files[1], &inputs).unwrap(),
+            out_dir.join("nested/c.txt")
+        );
+        assert!(out_dir.join("nested").is_dir());
+        assert_eq!(
+            mirror_output_path(&out_dir, &files[2], &inputs).unwrap(),
+            out_dir.join("single.txt")
+        );
+
+        // ignored directories aren't walked, and the ignore file itself is never included
+        fs::write(root.join("in/.ironhideignore"), "nested/\n").unwrap();
+        assert_eq!(
+            collect_files(&inputs, true, &no_filter, |_| true).unwrap(),
+            vec![
+                root.join("in/a.txt"),
+                root.join("in/b.txt.iron"),
+                root.join("single.txt")
+            ]
+        );
+        // they apply to walks starting beneath them, but not to files given explicitly
+        let nested = vec![root.join("in/nested/c.txt"), root.join("in/a.txt")];
+        assert_eq!(
+            collect_files(&nested, false, &no_filter, |_| true).unwrap(),
+            nested
+        );
+        assert!(
+            collect_files(&[root.join("in/nested")], true, &no_filter, |_| true)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_prepare_output_dir_and_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let out_dir = root.join("out");
+        assert!(prepare_output_dir(&out_dir, false).is_err());
+        prepare_output_dir(&out_dir, true).unwrap();
+        assert!(out_dir.is_dir());
+        fs::write(root.join("file"), "f").unwrap();
+        assert!(prepare_output_dir(&root.join("file"), true).is_err());
+
+        let inputs = vec![
+            PathBuf::from("a/x.iron"),
+            PathBuf::from("b/x.iron"),
+            PathBuf::from("y.iron"),
+        ];
+        let distinct = vec![
+            PathBuf::from("out/a/x"),
+            PathBuf::from("out/b/x"),
+            PathBuf::from("out/y"),
+        ];
+        assert!(check_output_collisions(&inputs, &distinct).is_ok());
+        let colliding = vec![
+            PathBuf::from("out/x"),
+            PathBuf::from("out/x"),
+            PathBuf::from("out/y"),
+        ];
+        let err = check_output_collisions(&inputs, &colliding).unwrap_err();
+        assert!(err.contains("'out/x' would be written from 'a/x.iron', 'b/x.iron'"));
+        assert!(!err.contains("out/y"));
+        let err = check_output_collisions(&inputs[2..], &inputs[2..]).unwrap_err();
+        assert!(err.contains("'y.iron' would be written over itself"));
+    }
+
+    #[test]
+    fn sha256_reader_measures_what_was_read() {
+        use std::io::Read;
+        let mut reader = Sha256Reader::new(&b"hello world"[..]);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        let (len, digest) = reader.finish();
+        assert_eq!(len, 11);
+        assert_eq!(
+            to_hex(&digest),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn foreground_child_can_be_interrupted_and_gets_forwarded_signals() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::Command;
+        // the child doesn't inherit this process ignoring Ctrl-C
+        let exit =
+            run_in_foreground(Command::new("sh").args(["-c", "kill -INT $$; exit 0"])).unwrap();
+        assert_eq!(exit.status.signal(), Some(libc::SIGINT));
+        assert!(!exit.terminated);
+        // SIGTERM sent to this process reaches the child
+        let exit =
+            run_in_foreground(Command::new("sh").args(["-c", "kill -TERM $PPID; exec sleep 10"]))
+                .unwrap();
+        assert_eq!(exit.status.signal(), Some(libc::SIGTERM));
+        assert!(exit.terminated);
+    }
 }